@@ -0,0 +1,465 @@
+use std::fmt::{self, Write};
+
+use clap::ValueEnum;
+use handlebars::{RenderError, RenderErrorReason, TemplateError};
+use serde::Serialize;
+
+use crate::error::AppError;
+use crate::vars::Origin;
+
+/// Lines of context shown above and below the offending line in a code frame.
+const FRAME_CONTEXT: usize = 2;
+
+/// How diagnostics are written to stderr.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum DiagnosticsFormat {
+    /// Readable messages with code frames
+    Human,
+    /// One JSON object per line, meant for editors and CI annotators
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Info,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Info => write!(f, "info"),
+        }
+    }
+}
+
+/// A user facing report about a problem found in a template or a vars file.
+#[derive(Debug, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Short, stable identifier of the kind of problem (`template`, `vars`, `io`, `render`).
+    pub code: &'static str,
+    pub file: Option<String>,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Name of the helper involved, if any.
+    pub helper: Option<String>,
+    pub message: String,
+    pub hint: Option<String>,
+    /// Pre-rendered excerpt of the file around `line`/`column`.
+    #[serde(skip)]
+    pub frame: Option<String>,
+}
+
+impl Diagnostic {
+    fn error(code: &'static str, file: &str, message: String) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            file: Some(file.to_owned()),
+            line: None,
+            column: None,
+            helper: None,
+            message,
+            hint: None,
+            frame: None,
+        }
+    }
+
+    /// Writes the diagnostic to stderr in the given format.
+    pub fn emit(&self, format: DiagnosticsFormat) {
+        match format {
+            DiagnosticsFormat::Human => eprint!("{self}"),
+            DiagnosticsFormat::Json => match serde_json::to_string(self) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprint!("{self}"),
+            },
+        }
+    }
+
+    /// Points the diagnostic at `line`/`column` (both 1-based) of `contents`.
+    fn at(self, contents: &str, line: Option<usize>, column: Option<usize>) -> Self {
+        self.at_excerpt(contents, 1, line, column)
+    }
+
+    /// Like [`Diagnostic::at`], for `contents` that start at line `first_line` of the file.
+    fn at_excerpt(
+        mut self,
+        contents: &str,
+        first_line: usize,
+        line: Option<usize>,
+        column: Option<usize>,
+    ) -> Self {
+        self.line = line;
+        self.column = column;
+        if let Some(line) = line {
+            self.frame = Some(code_frame(contents, first_line, line, column));
+        }
+        self
+    }
+
+    fn with_helper(mut self, helper: Option<String>) -> Self {
+        self.hint = helper.as_deref().and_then(helper_usage).map(|usage| {
+            let expected = self.message_expected_type();
+            match expected {
+                Some(expected) => format!("{expected}; usage: {usage}"),
+                None => format!("usage: {usage}"),
+            }
+        });
+        self.helper = helper;
+        self
+    }
+
+    /// Extracts a friendly "expected ..." sentence from type mismatch messages.
+    fn message_expected_type(&self) -> Option<String> {
+        let (_, rest) = self.message.split_once(" param with name ")?;
+        let (param, tpe) = rest.split_once(" type mismatch for ")?;
+        Some(format!(
+            "parameter `{param}` must be {}",
+            friendly_type_name(tpe)
+        ))
+    }
+}
+
+impl From<&AppError> for Diagnostic {
+    fn from(err: &AppError) -> Self {
+        match err {
+            AppError::Template {
+                path,
+                contents,
+                source,
+            } => template_diagnostic(path, contents, source),
+            AppError::PartialConflict { name, path, other } => Diagnostic::error(
+                "template",
+                path,
+                format!("partial `{name}` clashes with {other}"),
+            ),
+            AppError::UnknownLayout { name, path } => {
+                let mut diagnostic = Diagnostic::error(
+                    "template",
+                    path,
+                    format!("layout `{name}` isn't a registered partial"),
+                );
+                diagnostic.hint = Some(format!(
+                    "pass the directory holding `{name}` or `layouts/{name}` with --partials"
+                ));
+                diagnostic
+            }
+            AppError::Vars {
+                path,
+                contents,
+                source,
+            } => Diagnostic::error("vars", path, source.to_string()).at(
+                contents,
+                source.line,
+                source.column,
+            ),
+            AppError::Record {
+                path,
+                line,
+                contents,
+                source,
+            } => Diagnostic::error("vars", path, source.to_string()).at_excerpt(
+                contents,
+                *line,
+                Some(*line),
+                source.column,
+            ),
+            AppError::VarsShape { option, message } => {
+                Diagnostic::error("vars", option, message.clone())
+            }
+            AppError::OutputPath { path, message } => {
+                Diagnostic::error("render", path, message.clone())
+            }
+            AppError::Io { path, source } => Diagnostic::error("io", path, source.to_string()),
+            AppError::Render {
+                path,
+                contents,
+                source,
+            } => render_diagnostic(path, contents.as_deref(), source),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}[{}]: {}", self.severity, self.code, self.message)?;
+        if let Some(file) = &self.file {
+            match (self.line, self.column) {
+                (Some(line), Some(column)) => writeln!(f, "  --> {file}:{line}:{column}")?,
+                (Some(line), None) => writeln!(f, "  --> {file}:{line}")?,
+                _ => writeln!(f, "  --> {file}")?,
+            }
+        }
+        if let Some(frame) = &self.frame {
+            write!(f, "{frame}")?;
+        }
+        if let Some(helper) = &self.helper {
+            writeln!(f, "   = helper: {helper}")?;
+        }
+        if let Some(hint) = &self.hint {
+            writeln!(f, "   = hint: {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes where a value came from (`--explain-var`) to stderr. In JSON it's an `info` object
+/// with code `explain`, so it fits in the same stream as the diagnostics.
+pub fn emit_origin(origin: &Origin, format: DiagnosticsFormat) {
+    #[derive(Serialize)]
+    struct Explanation<'a> {
+        severity: Severity,
+        code: &'static str,
+        #[serde(flatten)]
+        origin: &'a Origin,
+    }
+
+    match format {
+        DiagnosticsFormat::Human => eprintln!("{origin}"),
+        DiagnosticsFormat::Json => {
+            let explanation = Explanation {
+                severity: Severity::Info,
+                code: "explain",
+                origin,
+            };
+            match serde_json::to_string(&explanation) {
+                Ok(json) => eprintln!("{json}"),
+                Err(_) => eprintln!("{origin}"),
+            }
+        }
+    }
+}
+
+fn template_diagnostic(path: &str, contents: &str, err: &TemplateError) -> Diagnostic {
+    let (line, column) = err.pos().unzip();
+    Diagnostic::error("template", path, err.reason().to_string()).at(contents, line, column)
+}
+
+fn render_diagnostic(path: &str, contents: Option<&str>, err: &RenderError) -> Diagnostic {
+    let diagnostic = Diagnostic::error("render", path, err.reason().to_string());
+    let Some(contents) = contents else {
+        let helper = helper_name(err.reason()).map(str::to_owned);
+        return diagnostic.with_helper(helper);
+    };
+    let helper = helper_name(err.reason())
+        .map(str::to_owned)
+        .or_else(|| expression_helper(contents, err.line_no, err.column_no));
+    diagnostic
+        .at(contents, err.line_no, err.column_no)
+        .with_helper(helper)
+}
+
+/// Name of the helper that raised `reason`, when handlebars tells us.
+fn helper_name(reason: &RenderErrorReason) -> Option<&str> {
+    match reason {
+        RenderErrorReason::ParamNotFoundForIndex(name, _)
+        | RenderErrorReason::ParamNotFoundForName(name, _)
+        | RenderErrorReason::ParamTypeMismatchForName(name, _, _)
+        | RenderErrorReason::HashTypeMismatchForName(name, _, _) => Some(registered_name(name)),
+        RenderErrorReason::HelperNotFound(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// `handlebars_helper!` reports the struct name, which isn't always the registered one.
+fn registered_name(struct_name: &str) -> &str {
+    match struct_name {
+        "modulus" => "mod",
+        name => name,
+    }
+}
+
+/// Falls back to reading the helper name out of the expression at `line`/`column`.
+fn expression_helper(contents: &str, line: Option<usize>, column: Option<usize>) -> Option<String> {
+    let line = contents.lines().nth(line?.checked_sub(1)?)?;
+    let expr: String = line.chars().skip(column?.saturating_sub(1)).collect();
+    let expr = expr.strip_prefix("{{")?;
+    let expr = expr.trim_start_matches(['{', '#', '~', '^', ' ']);
+    let name: String = expr
+        .chars()
+        .take_while(|c| !c.is_whitespace() && *c != '}' && *c != '~')
+        .collect();
+    helper_usage(&name).map(|_| name)
+}
+
+/// Renders `line` (1-based) of `contents` with some context, underlining either the
+/// `{{...}}` expression that starts at `column` or just the column itself.
+///
+/// `first_line` is the number of the first line of `contents`, for excerpts of bigger files.
+fn code_frame(contents: &str, first_line: usize, line: usize, column: Option<usize>) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let first = line.saturating_sub(FRAME_CONTEXT).max(first_line);
+    // errors at EOF may point one line past the last one
+    let last = (line + FRAME_CONTEXT).min((first_line + lines.len()).saturating_sub(1).max(line));
+    let width = last.to_string().len();
+
+    let mut buf = String::new();
+    let _ = writeln!(buf, "{:width$} |", "");
+    for number in first..=last {
+        let content = lines.get(number - first_line).copied().unwrap_or_default();
+        let _ = writeln!(buf, "{number:>width$} | {content}");
+        if number == line {
+            if let Some(column) = column {
+                let start = column.saturating_sub(1);
+                let len = expression_len(content, start);
+                let _ = writeln!(
+                    buf,
+                    "{:width$} | {}{}",
+                    "",
+                    " ".repeat(start),
+                    "^".repeat(len)
+                );
+            }
+        }
+    }
+    let _ = writeln!(buf, "{:width$} |", "");
+    buf
+}
+
+/// Length in chars of the `{{...}}` expression starting at char `start`, or 1 if there's none.
+fn expression_len(line: &str, start: usize) -> usize {
+    let rest: String = line.chars().skip(start).collect();
+    if !rest.starts_with("{{") {
+        return 1;
+    }
+    match rest.find("}}") {
+        Some(end) => {
+            let end = end + 2 + rest[end + 2..].chars().take_while(|c| *c == '}').count();
+            rest[..end].chars().count()
+        }
+        None => rest.chars().count(),
+    }
+}
+
+fn friendly_type_name(tpe: &str) -> &str {
+    match tpe {
+        "isize" | "i64" => "an integer",
+        "usize" | "u64" => "a non-negative integer",
+        "f64" => "a number",
+        "bool" => "a boolean",
+        "String" | "str" => "a string",
+        "Vec<String>" => "an array of strings",
+        "DateTime<Utc>" => "an RFC 3339 date string (e.g. 2024-01-31T10:00:00Z)",
+        "Value" | "Json" => "any value",
+        "object" => "an object",
+        "array" => "an array",
+        other => other,
+    }
+}
+
+/// Usage line of every helper templatier registers.
+fn helper_usage(name: &str) -> Option<&'static str> {
+    let usage = match name {
+        "add" => "{{add <integer> <integer>}}",
+        "sub" => "{{sub <integer> <integer>}}",
+        "mul" => "{{mul <integer> <integer>}}",
+        "div" => "{{div <integer> <integer>}}",
+        "mod" => "{{mod <integer> <integer>}}",
+        "max" => "{{max <integer> <integer>}}",
+        "floor" => "{{floor <number>}}",
+        "ceil" => "{{ceil <number>}}",
+        "round" => "{{round <number>}}",
+        "rand_int" => "{{rand_int}}",
+        "lt" => "{{lt <integer> <integer>}}",
+        "le" => "{{le <integer> <integer>}}",
+        "gt" => "{{gt <integer> <integer>}}",
+        "ge" => "{{ge <integer> <integer>}}",
+        "eq" => "{{eq <any> <any>}}",
+        "ne" => "{{ne <any> <any>}}",
+        "upper" => "{{upper <string>}}",
+        "lower" => "{{lower <string>}}",
+        "trunc" => "{{trunc <length> <string>}}",
+        "abbrev" => "{{abbrev <length> <string>}}",
+        "plural" => "{{plural <count> <singular> <plural>}}",
+        "trim" => "{{trim <string>}}",
+        "join" => "{{join <delimiter> <array of strings>}}",
+        "split" => "{{split <delimiter> <string>}}",
+        "splitn" => "{{splitn <delimiter> <count> <string>}}",
+        "sort_alpha" => "{{sort_alpha <array of strings>}}",
+        "trim_suffix" => "{{trim_suffix <suffix> <string>}}",
+        "trim_prefix" => "{{trim_prefix <prefix> <string>}}",
+        "trim_all" => "{{trim_all <substring> <string>}}",
+        "date_format" => "{{date_format <format> <RFC 3339 date>}}",
+        "now" => "{{now <format>}}",
+        "env" => "{{env <name> [default=<string>]}}",
+        "skip_file" => "{{skip_file [if=<condition>] [unless=<condition>]}}",
+        "or" => "{{or <any> <any> [include_zero=<boolean>]}}",
+        "and" => "{{and <boolean> <boolean>}}",
+        "not" => "{{not <boolean>}}",
+        "xor" => "{{xor <boolean> <boolean>}}",
+        "isdef" => "{{isdef <path>}}",
+        "isdef_pass" => "{{isdef_pass <path> [includeZeros=<boolean>]}}",
+        "isundef" => "{{isundef <path>}}",
+        _ => return None,
+    };
+    Some(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{code_frame, Diagnostic};
+    use crate::error::AppError;
+    use crate::{build_hb_registry, TPLT};
+
+    #[test]
+    fn frame_underlines_whole_expression() {
+        let frame = code_frame("a\nb {{upper x}} c\nd", 1, 2, Some(3));
+        assert_eq!(
+            frame,
+            "  |\n1 | a\n2 | b {{upper x}} c\n  |   ^^^^^^^^^^^\n3 | d\n  |\n"
+        );
+    }
+
+    #[test]
+    fn render_error_names_helper() {
+        let tpl = "{{add a b}}";
+        let hb = build_hb_registry(tpl, false).expect("couldn't build template");
+        let source = hb
+            .render(TPLT, &json!({"a": "x", "b": 1}))
+            .expect_err("render should fail");
+        let err = AppError::Render {
+            path: "tpl.hbs".to_owned(),
+            contents: Some(tpl.to_owned()),
+            source,
+        };
+
+        let diagnostic = Diagnostic::from(&err);
+        assert_eq!(diagnostic.helper.as_deref(), Some("add"));
+        assert_eq!((diagnostic.line, diagnostic.column), (Some(1), Some(1)));
+        assert!(diagnostic
+            .hint
+            .expect("should have a hint")
+            .contains("must be an integer"));
+    }
+
+    #[test]
+    fn json_diagnostic_fields() {
+        let source = serde_json::from_str::<serde_json::Value>("{\n  \"a\": }")
+            .expect_err("vars should be invalid");
+        let err = AppError::Vars {
+            path: "vars.json".to_owned(),
+            contents: "{\n  \"a\": }".to_owned(),
+            source: source.into(),
+        };
+
+        let json = serde_json::to_value(Diagnostic::from(&err)).expect("couldn't serialize");
+        assert_eq!(
+            json,
+            json!({
+                "severity": "error",
+                "code": "vars",
+                "file": "vars.json",
+                "line": 2,
+                "column": 8,
+                "helper": null,
+                "message": "expected value at line 2 column 8",
+                "hint": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,157 @@
+use std::io;
+use std::process::ExitCode;
+
+use handlebars::{RenderError, TemplateError};
+use thiserror::Error;
+
+/// Everything that can go wrong while running templatier.
+///
+/// Each variant maps to its own process exit code (see [`AppError::exit_code`]) so callers
+/// (CI jobs, shell pipelines) can tell what failed without parsing stderr.
+#[derive(Debug, Error)]
+pub enum AppError {
+    /// The template couldn't be parsed by handlebars.
+    #[error("couldn't parse template \"{path}\": {source}")]
+    Template {
+        path: String,
+        /// Template source, kept around to show where the error is.
+        contents: String,
+        #[source]
+        source: Box<TemplateError>,
+    },
+    /// Two partials (or a partial and a template) ended up with the same name.
+    #[error("partial \"{name}\" from {path} clashes with {other}")]
+    PartialConflict {
+        name: String,
+        path: String,
+        /// What the partial clashes with.
+        other: String,
+    },
+    /// The layout a template asks for isn't among the registered partials.
+    #[error("{path}: layout \"{name}\" isn't a registered partial")]
+    UnknownLayout { name: String, path: String },
+    /// The vars file isn't valid.
+    #[error("couldn't parse vars file \"{path}\": {source}")]
+    Vars {
+        path: String,
+        contents: String,
+        #[source]
+        source: VarsError,
+    },
+    /// A record of an `--ndjson` stream isn't valid JSON.
+    #[error("couldn't parse record on line {line} of \"{path}\": {source}")]
+    Record {
+        path: String,
+        /// 1-based line of the record in the stream.
+        line: usize,
+        /// The record itself, the rest of the stream isn't kept.
+        contents: String,
+        #[source]
+        source: VarsError,
+    },
+    /// The vars don't have the shape a command line option needs, e.g. `--for-each` pointing at
+    /// something that isn't an array.
+    #[error("{option}: {message}")]
+    VarsShape { option: String, message: String },
+    /// A templated file or directory name didn't render to a usable name.
+    #[error("{path}: {message}")]
+    OutputPath { path: String, message: String },
+    /// A file couldn't be read or written.
+    #[error("{path}: {source}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+    /// The template was parsed fine but failed while rendering.
+    #[error("couldn't render template \"{path}\": {source}")]
+    Render {
+        path: String,
+        /// Template source, kept around to show where the error is. `None` when the error was
+        /// raised in a template whose source isn't known.
+        contents: Option<String>,
+        #[source]
+        source: RenderError,
+    },
+}
+
+/// Why a vars file couldn't be parsed, and where when the parser tells us.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct VarsError {
+    pub message: String,
+    /// 1-based line of the problem.
+    pub line: Option<usize>,
+    /// 1-based column of the problem.
+    pub column: Option<usize>,
+}
+
+impl VarsError {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            line: None,
+            column: None,
+        }
+    }
+
+    pub fn at(mut self, line: usize, column: usize) -> Self {
+        self.line = Some(line);
+        self.column = Some(column);
+        self
+    }
+
+    /// Points the error at byte `offset` of `contents`.
+    pub fn at_offset(self, contents: &str, offset: usize) -> Self {
+        let (mut line, mut column) = (1, 1);
+        for (_, c) in contents.char_indices().take_while(|(i, _)| *i < offset) {
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
+        self.at(line, column)
+    }
+}
+
+impl From<serde_json::Error> for VarsError {
+    fn from(e: serde_json::Error) -> Self {
+        VarsError::new(e.to_string()).at(e.line(), e.column())
+    }
+}
+
+impl AppError {
+    /// Exit code used when rendering fails.
+    pub const RENDER_EXIT_CODE: u8 = 1;
+    /// Exit code used when the template can't be parsed.
+    ///
+    /// `2` is skipped on purpose: clap already uses it for invalid command line usage.
+    pub const TEMPLATE_EXIT_CODE: u8 = 3;
+    /// Exit code used when the vars can't be parsed.
+    pub const VARS_EXIT_CODE: u8 = 4;
+    /// Exit code used when a file can't be read or written.
+    pub const IO_EXIT_CODE: u8 = 5;
+
+    pub fn io(path: impl Into<String>, source: io::Error) -> Self {
+        Self::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    pub fn exit_code(&self) -> ExitCode {
+        let code = match self {
+            Self::Template { .. } | Self::PartialConflict { .. } | Self::UnknownLayout { .. } => {
+                Self::TEMPLATE_EXIT_CODE
+            }
+            Self::Vars { .. } | Self::Record { .. } | Self::VarsShape { .. } => {
+                Self::VARS_EXIT_CODE
+            }
+            Self::Io { .. } => Self::IO_EXIT_CODE,
+            Self::Render { .. } | Self::OutputPath { .. } => Self::RENDER_EXIT_CODE,
+        };
+        ExitCode::from(code)
+    }
+}
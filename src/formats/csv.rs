@@ -0,0 +1,122 @@
+use serde_json::{Map, Value};
+
+use super::{has_leading_zero, FormatOptions};
+use crate::error::VarsError;
+
+impl From<csv::Error> for VarsError {
+    fn from(e: csv::Error) -> Self {
+        // csv only knows the line of the record, not the column
+        VarsError {
+            line: e.position().map(|position| position.line() as usize),
+            ..VarsError::new(e.to_string())
+        }
+    }
+}
+
+/// Parses a CSV (or, with a tab `delimiter`, TSV) file with a header row into an array of
+/// objects keyed by the header, exposed under `options.csv_root`.
+///
+/// With `options.infer_types` cells that look like integers, floats or booleans get that type
+/// and empty cells become `null`; otherwise every cell is a string.
+pub fn parse(contents: &str, delimiter: u8, options: &FormatOptions) -> Result<Value, VarsError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(contents.as_bytes());
+    let headers = reader.headers()?.clone();
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let row = headers
+            .iter()
+            .zip(record.iter())
+            .map(|(header, cell)| {
+                let value = if options.infer_types {
+                    infer_type(cell)
+                } else {
+                    Value::String(cell.to_owned())
+                };
+                (header.to_owned(), value)
+            })
+            .collect::<Map<_, _>>();
+        rows.push(Value::Object(row));
+    }
+
+    let mut root = Map::new();
+    root.insert(options.csv_root.clone(), Value::Array(rows));
+    Ok(Value::Object(root))
+}
+
+fn infer_type(cell: &str) -> Value {
+    let trimmed = cell.trim();
+    match trimmed {
+        "" => Value::Null,
+        "true" | "TRUE" | "True" => Value::Bool(true),
+        "false" | "FALSE" | "False" => Value::Bool(false),
+        _ if has_leading_zero(trimmed) => Value::String(cell.to_owned()),
+        _ => {
+            if let Ok(n) = trimmed.parse::<i64>() {
+                Value::from(n)
+            } else if let Some(n) = trimmed
+                .parse::<f64>()
+                .ok()
+                .and_then(serde_json::Number::from_f64)
+            {
+                Value::Number(n)
+            } else {
+                Value::String(cell.to_owned())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+    use crate::formats::FormatOptions;
+
+    #[test]
+    fn rows_become_objects_keyed_by_header() {
+        let value = parse(
+            "host,port\nweb1,80\n\"db, primary\",5432\n",
+            b',',
+            &FormatOptions::default(),
+        )
+        .expect("valid csv");
+        assert_eq!(
+            value,
+            json!({"rows": [
+                {"host": "web1", "port": "80"},
+                {"host": "db, primary", "port": "5432"},
+            ]})
+        );
+    }
+
+    #[test]
+    fn tsv_with_type_inference_and_custom_root() {
+        let options = FormatOptions {
+            csv_root: "hosts".to_owned(),
+            infer_types: true,
+        };
+        let value = parse(
+            "name\tport\tweight\tactive\tzip\tnote\na\t80\t0.5\ttrue\t01234\t\n",
+            b'\t',
+            &options,
+        )
+        .expect("valid tsv");
+        assert_eq!(
+            value,
+            json!({"hosts": [
+                {"name": "a", "port": 80, "weight": 0.5, "active": true, "zip": "01234", "note": null},
+            ]})
+        );
+    }
+
+    #[test]
+    fn ragged_rows_are_errors_with_a_line() {
+        let err = parse("a,b\n1,2\n3\n", b',', &FormatOptions::default()).expect_err("invalid csv");
+        assert_eq!(err.line, Some(3));
+    }
+}
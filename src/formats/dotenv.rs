@@ -0,0 +1,299 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+use serde_json::{Map, Value};
+
+use crate::error::VarsError;
+
+/// Parses a `.env` file into a flat object of strings.
+///
+/// Supports `#` comments, an optional `export` prefix, single quoted values (taken literally),
+/// double quoted values (with `\n`, `\t`, `\"`, `\\` and `\$` escapes, possibly spanning several
+/// lines) and unquoted values (trimmed, a ` #` starts a comment). `${VAR}`, `${VAR:-default}` and
+/// `$VAR` are expanded in double quoted and unquoted values, looking first at the variables
+/// defined earlier in the file and then at the process environment.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let mut parser = Parser {
+        chars: contents.chars().peekable(),
+        line: 1,
+        column: 1,
+        vars: Map::new(),
+    };
+    parser.parse()?;
+    Ok(Value::Object(parser.vars))
+}
+
+struct Parser<'a> {
+    chars: Peekable<Chars<'a>>,
+    line: usize,
+    column: usize,
+    vars: Map<String, Value>,
+}
+
+impl Parser<'_> {
+    fn parse(&mut self) -> Result<(), VarsError> {
+        loop {
+            self.skip_blanks();
+            match self.chars.peek() {
+                None => return Ok(()),
+                Some('\n') => {
+                    self.next();
+                }
+                Some('#') => self.skip_line(),
+                Some(_) => self.assignment()?,
+            }
+        }
+    }
+
+    fn assignment(&mut self) -> Result<(), VarsError> {
+        let mut key = self.word();
+        if key == "export" && self.chars.peek().is_some_and(|c| *c == ' ' || *c == '\t') {
+            self.skip_blanks();
+            key = self.word();
+        }
+        if key.is_empty() {
+            return Err(self.error("expected a variable name"));
+        }
+        self.skip_blanks();
+        if self.chars.peek() != Some(&'=') {
+            return Err(self.error(format!("expected `=` after {key}")));
+        }
+        self.next();
+        self.skip_blanks();
+
+        let value = match self.chars.peek() {
+            Some('\'') => self.single_quoted()?,
+            Some('"') => self.double_quoted()?,
+            _ => self.unquoted()?,
+        };
+        self.vars.insert(key, Value::String(value));
+        Ok(())
+    }
+
+    fn single_quoted(&mut self) -> Result<String, VarsError> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => break,
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated single quoted value")),
+            }
+        }
+        self.end_of_value()?;
+        Ok(value)
+    }
+
+    fn double_quoted(&mut self) -> Result<String, VarsError> {
+        self.next();
+        let mut value = String::new();
+        loop {
+            match self.next() {
+                Some('"') => break,
+                Some('\\') => match self.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(c @ ('"' | '\\' | '$' | '\'')) => value.push(c),
+                    Some(c) => {
+                        value.push('\\');
+                        value.push(c);
+                    }
+                    None => return Err(self.error("unterminated double quoted value")),
+                },
+                Some('$') => self.expand(&mut value)?,
+                Some(c) => value.push(c),
+                None => return Err(self.error("unterminated double quoted value")),
+            }
+        }
+        self.end_of_value()?;
+        Ok(value)
+    }
+
+    fn unquoted(&mut self) -> Result<String, VarsError> {
+        let mut value = String::new();
+        let mut after_blank = true;
+        while let Some(&c) = self.chars.peek() {
+            if c == '\n' || (c == '#' && after_blank) {
+                break;
+            }
+            self.next();
+            if c == '$' {
+                self.expand(&mut value)?;
+            } else {
+                value.push(c);
+            }
+            after_blank = c == ' ' || c == '\t';
+        }
+        self.skip_line();
+        Ok(value.trim_end().to_owned())
+    }
+
+    /// Expands the variable reference that follows a `$` into `value`.
+    fn expand(&mut self, value: &mut String) -> Result<(), VarsError> {
+        let (name, default) = if self.chars.peek() == Some(&'{') {
+            self.next();
+            let name = self.var_name();
+            let default = if self.chars.peek() == Some(&':') {
+                self.next();
+                if self.next() != Some('-') {
+                    return Err(self.error("expected `:-` in variable default"));
+                }
+                let mut default = String::new();
+                while let Some(&c) = self.chars.peek() {
+                    if c == '}' || c == '\n' {
+                        break;
+                    }
+                    self.next();
+                    default.push(c);
+                }
+                Some(default)
+            } else {
+                None
+            };
+            if self.next() != Some('}') {
+                return Err(self.error("unterminated `${`"));
+            }
+            (name, default)
+        } else {
+            (self.var_name(), None)
+        };
+
+        if name.is_empty() {
+            value.push('$');
+            return Ok(());
+        }
+        let resolved = self
+            .vars
+            .get(&name)
+            .and_then(|v| v.as_str().map(str::to_owned))
+            .or_else(|| std::env::var(&name).ok())
+            .filter(|v| default.is_none() || !v.is_empty());
+        value.push_str(&resolved.or(default).unwrap_or_default());
+        Ok(())
+    }
+
+    /// After a quoted value only blanks and a comment may follow on the same line.
+    fn end_of_value(&mut self) -> Result<(), VarsError> {
+        self.skip_blanks();
+        match self.chars.peek() {
+            None | Some('\n') => Ok(()),
+            Some('#') => {
+                self.skip_line();
+                Ok(())
+            }
+            Some(_) => Err(self.error("unexpected characters after quoted value")),
+        }
+    }
+
+    /// Reads the name of a variable being defined (letters, digits, `_`, `.` and `-`).
+    fn word(&mut self) -> String {
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '-')
+    }
+
+    /// Reads the name of a variable being expanded (letters, digits and `_`).
+    fn var_name(&mut self) -> String {
+        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_')
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !accept(c) {
+                break;
+            }
+            self.next();
+            taken.push(c);
+        }
+        taken
+    }
+
+    fn skip_blanks(&mut self) {
+        while matches!(self.chars.peek(), Some(' ' | '\t' | '\r')) {
+            self.next();
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while self.chars.peek().is_some_and(|c| *c != '\n') {
+            self.next();
+        }
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: impl Into<String>) -> VarsError {
+        VarsError::new(message).at(self.line, self.column)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn quoting_comments_and_export() {
+        let value = parse(
+            "# database\nexport DB_HOST=localhost # inline comment\nDB_PASS='p@ss#word $x'\nGREETING=\"hello\\nworld\"\nEMPTY=\nURL=http://x/#anchor\n",
+        )
+        .expect("valid dotenv");
+        assert_eq!(
+            value,
+            json!({
+                "DB_HOST": "localhost",
+                "DB_PASS": "p@ss#word $x",
+                "GREETING": "hello\nworld",
+                "EMPTY": "",
+                "URL": "http://x/#anchor",
+            })
+        );
+    }
+
+    #[test]
+    fn interpolation() {
+        let path = std::env::var("PATH").expect("PATH is set");
+        let value = parse(
+            "HOST=db\nURL=\"postgres://${HOST}:5432\"\nSUFFIXED=$HOST-1\nFROM_ENV=$PATH\nMISSING=${TEMPLATIER_TEST_UNSET:-fallback}\nLITERAL='${HOST}'\n",
+        )
+        .expect("valid dotenv");
+        assert_eq!(
+            value,
+            json!({
+                "HOST": "db",
+                "URL": "postgres://db:5432",
+                "SUFFIXED": "db-1",
+                "FROM_ENV": path,
+                "MISSING": "fallback",
+                "LITERAL": "${HOST}",
+            })
+        );
+    }
+
+    #[test]
+    fn multiline_double_quoted_values() {
+        let value =
+            parse("KEY=\"-----BEGIN-----\nabc\n-----END-----\"\nNEXT=1\n").expect("valid dotenv");
+        assert_eq!(value["KEY"], json!("-----BEGIN-----\nabc\n-----END-----"));
+        assert_eq!(value["NEXT"], json!("1"));
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("A=1\nB 2\n").expect_err("invalid dotenv");
+        assert_eq!((err.line, err.column), (Some(2), Some(3)));
+
+        let err = parse("A='unterminated\n").expect_err("invalid dotenv");
+        assert!(err.message.contains("unterminated"));
+    }
+}
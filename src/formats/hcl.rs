@@ -0,0 +1,194 @@
+use hcl::expr::{Expression, ObjectKey, TemplateExpr};
+use hcl::template::Element;
+use hcl::{Block, Body, Structure, Template};
+use serde_json::{Map, Value};
+
+use crate::error::VarsError;
+
+/// Parses an HCL document, such as a Terraform `.tfvars` file.
+///
+/// Attributes become keys and blocks become nested objects keyed by their identifier and then by
+/// each of their labels, so `service "web" { port = 80 }` is `{"service": {"web": {"port": 80}}}`.
+/// Blocks repeated under the same key become an array. Only literal expressions are accepted:
+/// variables, function calls, operators and interpolations can't be evaluated here.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let body = hcl::parse(contents).map_err(|e| match e {
+        hcl::Error::Parse(e) => {
+            VarsError::new(e.message()).at(e.location().line(), e.location().column())
+        }
+        e => VarsError::new(e.to_string()),
+    })?;
+    body_to_json(body)
+}
+
+fn body_to_json(body: Body) -> Result<Value, VarsError> {
+    let mut object = Map::new();
+    for structure in body {
+        match structure {
+            Structure::Attribute(attribute) => {
+                if object.contains_key(attribute.key.as_str()) {
+                    return Err(VarsError::new(format!(
+                        "attribute `{}` is defined more than once",
+                        attribute.key
+                    )));
+                }
+                let value = expr_to_json(attribute.expr, &attribute.key)?;
+                object.insert(attribute.key.into_inner(), value);
+            }
+            Structure::Block(block) => add_block(&mut object, block)?,
+        }
+    }
+    Ok(Value::Object(object))
+}
+
+fn add_block(object: &mut Map<String, Value>, block: Block) -> Result<(), VarsError> {
+    let mut keys = vec![block.identifier.into_inner()];
+    keys.extend(block.labels.into_iter().map(|label| label.into_inner()));
+    let body = body_to_json(block.body)?;
+
+    let (last, parents) = keys.split_last().expect("a block always has an identifier");
+    let mut target = object;
+    for key in parents {
+        let entry = target
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()));
+        target = match entry {
+            Value::Object(nested) => nested,
+            _ => return Err(conflict(&keys)),
+        };
+    }
+    match target.get_mut(last) {
+        None => {
+            target.insert(last.clone(), body);
+        }
+        Some(Value::Array(blocks)) => blocks.push(body),
+        Some(existing @ Value::Object(_)) => {
+            let first = existing.take();
+            *existing = Value::Array(vec![first, body]);
+        }
+        Some(_) => return Err(conflict(&keys)),
+    }
+    Ok(())
+}
+
+fn conflict(keys: &[String]) -> VarsError {
+    VarsError::new(format!(
+        "block `{}` clashes with an attribute of the same name",
+        keys.join(" ")
+    ))
+}
+
+fn expr_to_json(expr: Expression, name: &str) -> Result<Value, VarsError> {
+    Ok(match expr {
+        Expression::Null => Value::Null,
+        Expression::Bool(b) => Value::Bool(b),
+        Expression::Number(n) => serde_json::to_value(n).map_err(VarsError::from)?,
+        Expression::String(s) => Value::String(s),
+        Expression::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| expr_to_json(item, name))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expression::Object(entries) => Value::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| Ok((object_key(key, name)?, expr_to_json(value, name)?)))
+                .collect::<Result<_, VarsError>>()?,
+        ),
+        Expression::TemplateExpr(template) => Value::String(literal_template(&template, name)?),
+        Expression::Parenthesis(inner) => expr_to_json(*inner, name)?,
+        other => return Err(not_literal(&other, name)),
+    })
+}
+
+fn object_key(key: ObjectKey, name: &str) -> Result<String, VarsError> {
+    match key {
+        ObjectKey::Identifier(ident) => Ok(ident.into_inner()),
+        ObjectKey::Expression(Expression::String(s)) => Ok(s),
+        ObjectKey::Expression(other) => Err(not_literal(&other, name)),
+        _ => Err(VarsError::new(format!(
+            "`{name}` has an unsupported object key"
+        ))),
+    }
+}
+
+/// Heredocs are template expressions even when they don't interpolate anything.
+fn literal_template(template: &TemplateExpr, name: &str) -> Result<String, VarsError> {
+    let parsed = Template::from_expr(template).map_err(|e| VarsError::new(e.to_string()))?;
+    parsed
+        .elements()
+        .iter()
+        .map(|element| match element {
+            Element::Literal(literal) => Ok(literal.as_str()),
+            _ => Err(VarsError::new(format!(
+                "`{name}` uses template interpolation, only literal values are supported"
+            ))),
+        })
+        .collect()
+}
+
+fn not_literal(expr: &Expression, name: &str) -> VarsError {
+    let expr = hcl::format::to_string(expr).unwrap_or_default();
+    VarsError::new(format!(
+        "`{name}` = {expr} isn't a literal value, only literal values are supported"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn tfvars_attributes() {
+        let value = parse(
+            "region = \"eu-west-1\"\nreplicas = 3\nratio = 0.5\nenabled = true\nzones = [\"a\", \"b\"]\ntags = {\n  team = \"infra\"\n  \"cost-center\" = 42\n}\nmotd = <<EOT\nhello\nEOT\n",
+        )
+        .expect("valid hcl");
+        assert_eq!(
+            value,
+            json!({
+                "region": "eu-west-1",
+                "replicas": 3,
+                "ratio": 0.5,
+                "enabled": true,
+                "zones": ["a", "b"],
+                "tags": {"team": "infra", "cost-center": 42},
+                "motd": "hello\n",
+            })
+        );
+    }
+
+    #[test]
+    fn blocks_nest_by_labels() {
+        let value = parse(
+            "service \"web\" {\n  port = 80\n}\nservice \"db\" {\n  port = 5432\n}\nrule {\n  allow = 1\n}\nrule {\n  allow = 2\n}\n",
+        )
+        .expect("valid hcl");
+        assert_eq!(
+            value,
+            json!({
+                "service": {"web": {"port": 80}, "db": {"port": 5432}},
+                "rule": [{"allow": 1}, {"allow": 2}],
+            })
+        );
+    }
+
+    #[test]
+    fn only_literals_are_accepted() {
+        let err = parse("name = var.prefix\n").expect_err("not a literal");
+        assert!(
+            err.message.contains("only literal values"),
+            "{}",
+            err.message
+        );
+
+        let err = parse("name = \"${var.prefix}-x\"\n").expect_err("not a literal");
+        assert!(err.message.contains("interpolation"), "{}", err.message);
+
+        let err = parse("a = 1\nb = = 2\n").expect_err("invalid hcl");
+        assert_eq!(err.line, Some(2));
+    }
+}
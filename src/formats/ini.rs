@@ -0,0 +1,107 @@
+use serde_json::{Map, Value};
+
+use crate::error::VarsError;
+
+/// Parses an INI file. Keys before the first `[section]` go at the top level and the keys of
+/// every section go in an object named after it; repeated sections are merged.
+///
+/// Both `key = value` and `key: value` are accepted, lines starting with `;` or `#` are comments
+/// and values wrapped in double or single quotes lose them. Every value is a string.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let mut root = Map::new();
+    let mut section: Option<String> = None;
+
+    for (i, raw) in contents.lines().enumerate() {
+        let line = raw.trim();
+        let line_no = i + 1;
+        let column = raw.len() - raw.trim_start().len() + 1;
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(rest) = line.strip_prefix('[') {
+            let name = rest
+                .strip_suffix(']')
+                .ok_or_else(|| VarsError::new("unclosed section header").at(line_no, column))?
+                .trim();
+            if name.is_empty() {
+                return Err(VarsError::new("empty section name").at(line_no, column));
+            }
+            match root
+                .entry(name.to_owned())
+                .or_insert_with(|| Value::Object(Map::new()))
+            {
+                Value::Object(_) => section = Some(name.to_owned()),
+                _ => {
+                    return Err(VarsError::new(format!(
+                        "section [{name}] has the same name as a top level key"
+                    ))
+                    .at(line_no, column))
+                }
+            }
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once(['=', ':'])
+            .ok_or_else(|| VarsError::new("expected `key = value`").at(line_no, column))?;
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(VarsError::new("empty key").at(line_no, column));
+        }
+
+        let target = match &section {
+            Some(name) => match root.get_mut(name) {
+                Some(Value::Object(map)) => map,
+                _ => unreachable!("sections are always objects"),
+            },
+            None => &mut root,
+        };
+        target.insert(
+            key.to_owned(),
+            Value::String(unquote(value.trim()).to_owned()),
+        );
+    }
+    Ok(Value::Object(root))
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if let Some(inner) = value
+            .strip_prefix(quote)
+            .and_then(|v| v.strip_suffix(quote))
+        {
+            return inner;
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn sections_become_objects() {
+        let value = parse(
+            "; service config\nname = svc\n\n[database]\nhost = localhost\nport: 5432\npassword = \"a = b\"\n\n[paths]\nlogs=C:\\logs\n",
+        )
+        .expect("valid ini");
+        assert_eq!(
+            value,
+            json!({
+                "name": "svc",
+                "database": {"host": "localhost", "port": "5432", "password": "a = b"},
+                "paths": {"logs": "C:\\logs"},
+            })
+        );
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("[ok]\n  just some words\n").expect_err("invalid ini");
+        assert_eq!((err.line, err.column), (Some(2), Some(3)));
+    }
+}
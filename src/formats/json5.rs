@@ -0,0 +1,93 @@
+use serde_json::Value;
+
+use crate::error::VarsError;
+
+impl From<json5::Error> for VarsError {
+    fn from(e: json5::Error) -> Self {
+        let json5::Error::Message { msg, location } = e;
+        // syntax errors come with their own code frame; keep just the `= expected ...` part
+        let message = msg
+            .lines()
+            .find_map(|line| line.trim_start().strip_prefix("= "))
+            .unwrap_or(&msg)
+            .to_owned();
+        let error = VarsError::new(message);
+        match location {
+            Some(location) => error.at(location.line, location.column),
+            None => error,
+        }
+    }
+}
+
+/// Parses JSON5, which also covers JSONC: comments, trailing commas, unquoted keys, single
+/// quoted strings, hex numbers...
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    Ok(json5::from_str(contents)?)
+}
+
+/// Parses plain JSON, falling back to JSON5 so commented files work too. If both fail the JSON
+/// error is reported, since it's the more precise one, unless JSON5 got further into the file or
+/// the JSON error is just a comment.
+pub fn parse_json(contents: &str) -> Result<Value, VarsError> {
+    let json_error = match serde_json::from_str(contents) {
+        Ok(value) => return Ok(value),
+        Err(e) => VarsError::from(e),
+    };
+    let json5_error = match parse(contents) {
+        Ok(value) => return Ok(value),
+        Err(e) => e,
+    };
+    let position = |e: &VarsError| (e.line, e.column);
+    if position(&json5_error) > position(&json_error) || on_comment(contents, &json_error) {
+        Err(json5_error)
+    } else {
+        Err(json_error)
+    }
+}
+
+fn on_comment(contents: &str, error: &VarsError) -> bool {
+    let (Some(line), Some(column)) = (error.line, error.column) else {
+        return false;
+    };
+    contents
+        .lines()
+        .nth(line.saturating_sub(1))
+        .and_then(|text| text.get(column.saturating_sub(1)..))
+        .is_some_and(|rest| rest.starts_with("//") || rest.starts_with("/*"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{parse, parse_json};
+
+    #[test]
+    fn comments_and_trailing_commas() {
+        let contents = r#"{
+            // why this is set
+            "replicas": 3, /* block comment */
+            tags: ['a', 'b',],
+        }"#;
+        let expected = json!({"replicas": 3, "tags": ["a", "b"]});
+        assert_eq!(parse(contents).expect("valid json5"), expected);
+        assert_eq!(parse_json(contents).expect("valid jsonc"), expected);
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("{\n  a: 1,\n  b: ,\n}").expect_err("invalid json5");
+        assert_eq!(err.line, Some(3));
+        assert!(!err.message.contains('\n'));
+
+        let err = parse_json("{\n  \"a\": }").expect_err("invalid json");
+        assert_eq!((err.line, err.column), (Some(2), Some(8)));
+    }
+
+    #[test]
+    fn errors_after_a_comment_are_reported_by_json5() {
+        let err =
+            parse_json("{\n  // why\n  \"a\": 1,\n  \"tag\": ,\n}").expect_err("invalid jsonc");
+        assert_eq!(err.line, Some(4));
+    }
+}
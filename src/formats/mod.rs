@@ -0,0 +1,113 @@
+//! Parsers that turn vars files of every supported format into the JSON value handlebars renders.
+
+use std::path::Path;
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::error::VarsError;
+
+mod csv;
+mod dotenv;
+mod hcl;
+mod ini;
+mod json5;
+mod properties;
+mod toml;
+mod xml;
+mod yaml;
+
+/// Format of a vars file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum VarsFormat {
+    /// JSON; comments and trailing commas are accepted too
+    Json,
+    /// JSON5 (and JSONC)
+    Json5,
+    /// YAML; files with several documents become an array with one element per document
+    Yaml,
+    /// TOML; datetimes become RFC 3339 strings that `date_format` understands
+    Toml,
+    /// `.env` files; every variable becomes a string
+    Dotenv,
+    /// CSV with a header row; rows become objects keyed by the header
+    Csv,
+    /// Like csv, but separated by tabs
+    Tsv,
+    /// INI; sections become objects and every value is a string
+    Ini,
+    /// Java properties; dotted keys become nested objects and every value is a string
+    Properties,
+    /// XML; attributes go under `@name` keys and repeated elements become arrays
+    Xml,
+    /// HCL, such as Terraform `.tfvars`; blocks become objects keyed by their labels
+    Hcl,
+}
+
+/// Settings that only apply to some formats.
+#[derive(Debug, Clone)]
+pub struct FormatOptions {
+    /// Key under which CSV/TSV rows are exposed.
+    pub csv_root: String,
+    /// Whether CSV/TSV cells that look like numbers or booleans get that type.
+    pub infer_types: bool,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        Self {
+            csv_root: "rows".to_owned(),
+            infer_types: false,
+        }
+    }
+}
+
+impl VarsFormat {
+    /// Guesses the format of `path` from its name.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let path = Path::new(path);
+        // `.env`, `.env.local`, ...
+        let file_name = path.file_name()?.to_str()?;
+        if file_name == ".env" || file_name.starts_with(".env.") {
+            return Some(Self::Dotenv);
+        }
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "json" => Some(Self::Json),
+            "json5" | "jsonc" => Some(Self::Json5),
+            "yaml" | "yml" => Some(Self::Yaml),
+            "toml" => Some(Self::Toml),
+            "env" => Some(Self::Dotenv),
+            "csv" => Some(Self::Csv),
+            "tsv" | "tab" => Some(Self::Tsv),
+            "ini" => Some(Self::Ini),
+            "properties" => Some(Self::Properties),
+            "xml" => Some(Self::Xml),
+            "hcl" | "tfvars" => Some(Self::Hcl),
+            _ => None,
+        }
+    }
+
+    pub fn parse(self, contents: &str, options: &FormatOptions) -> Result<Value, VarsError> {
+        match self {
+            Self::Json => json5::parse_json(contents),
+            Self::Json5 => json5::parse(contents),
+            Self::Yaml => yaml::parse(contents),
+            Self::Toml => toml::parse(contents),
+            Self::Dotenv => dotenv::parse(contents),
+            Self::Csv => csv::parse(contents, b',', options),
+            Self::Tsv => csv::parse(contents, b'\t', options),
+            Self::Ini => ini::parse(contents),
+            Self::Properties => properties::parse(contents),
+            Self::Xml => xml::parse(contents),
+            Self::Hcl => hcl::parse(contents),
+        }
+    }
+}
+
+/// Whether `s` is a number written with leading zeros (`007`, `-01`), which usually means it's an
+/// identifier such as a zip code rather than a number, so it's better kept as a string.
+pub fn has_leading_zero(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    digits.len() > 1 && digits.starts_with('0') && !digits.starts_with("0.")
+}
@@ -0,0 +1,173 @@
+use serde_json::{Map, Value};
+
+use crate::error::VarsError;
+
+/// Parses a Java `.properties` file. Dotted keys become nested objects (`db.host=x` is
+/// `{"db": {"host": "x"}}`) and every value is a string.
+///
+/// Follows the `java.util.Properties` rules: `#` and `!` comments, `=`, `:` or whitespace as
+/// separator, `\t`, `\n`, `\r`, `\f` and `\uXXXX` escapes and lines ending in `\` continuing on
+/// the next one. A key that is both a value and the prefix of other keys (`a=1` and `a.b=2`) is
+/// an error.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let mut root = Map::new();
+    for (line_no, line) in logical_lines(contents) {
+        let (key, value) = split_key_value(&line);
+        let key = unescape(key).map_err(|e| VarsError::new(e).at(line_no, 1))?;
+        let value = unescape(value).map_err(|e| VarsError::new(e).at(line_no, 1))?;
+        insert(&mut root, &key, value).map_err(|e| VarsError::new(e).at(line_no, 1))?;
+    }
+    Ok(Value::Object(root))
+}
+
+/// Joins continued lines and drops blanks and comments, keeping the line number where each
+/// logical line starts.
+fn logical_lines(contents: &str) -> Vec<(usize, String)> {
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+
+    for (i, raw) in contents.lines().enumerate() {
+        let trimmed = raw.trim_start();
+        let (start, mut text) = match current.take() {
+            Some((start, text)) => (start, text),
+            None => {
+                if trimmed.is_empty() || trimmed.starts_with('#') || trimmed.starts_with('!') {
+                    continue;
+                }
+                (i + 1, String::new())
+            }
+        };
+
+        let trailing_backslashes = trimmed.chars().rev().take_while(|c| *c == '\\').count();
+        if trailing_backslashes % 2 == 1 {
+            text.push_str(&trimmed[..trimmed.len() - 1]);
+            current = Some((start, text));
+        } else {
+            text.push_str(trimmed);
+            lines.push((start, text));
+        }
+    }
+    if let Some(line) = current {
+        lines.push(line);
+    }
+    lines
+}
+
+/// Splits at the first unescaped `=`, `:` or whitespace.
+fn split_key_value(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '=' | ':' => return (&line[..i], line[i + 1..].trim_start()),
+            c if c.is_whitespace() => {
+                let rest = line[i..].trim_start();
+                let rest = rest
+                    .strip_prefix(['=', ':'])
+                    .map(str::trim_start)
+                    .unwrap_or(rest);
+                return (&line[..i], rest);
+            }
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+fn unescape(s: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                let code = u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .filter(|_| hex.len() == 4)
+                    .and_then(char::from_u32)
+                    .ok_or_else(|| format!("invalid unicode escape \\u{hex}"))?;
+                out.push(code);
+            }
+            Some(other) => out.push(other),
+            None => {}
+        }
+    }
+    Ok(out)
+}
+
+fn insert(root: &mut Map<String, Value>, key: &str, value: String) -> Result<(), String> {
+    let mut parts = key.split('.').peekable();
+    let mut target = root;
+    while let Some(part) = parts.next() {
+        if parts.peek().is_none() {
+            if matches!(target.get(part), Some(Value::Object(_))) {
+                return Err(format!("{key} is already used as a prefix of other keys"));
+            }
+            target.insert(part.to_owned(), Value::String(value));
+            return Ok(());
+        }
+        let next = target
+            .entry(part.to_owned())
+            .or_insert_with(|| Value::Object(Map::new()));
+        target = match next {
+            Value::Object(map) => map,
+            _ => {
+                return Err(format!(
+                    "{key} is nested under a key that already has a value"
+                ))
+            }
+        };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn dotted_keys_become_nested_objects() {
+        let value = parse(
+            "# app\n! also a comment\napp.name = My App\napp.db.url:jdbc:postgresql://host/db\napp.greeting Hello \\u00e9\\tworld\nkey\\=with\\:separators = yes\n",
+        )
+        .expect("valid properties");
+        assert_eq!(
+            value,
+            json!({
+                "app": {
+                    "name": "My App",
+                    "db": {"url": "jdbc:postgresql://host/db"},
+                    "greeting": "Hello é\tworld",
+                },
+                "key=with:separators": "yes",
+            })
+        );
+    }
+
+    #[test]
+    fn continuation_lines() {
+        let value = parse("fruits = apple, \\\n         banana, \\\n         pear\nnext=1\n")
+            .expect("valid properties");
+        assert_eq!(value, json!({"fruits": "apple, banana, pear", "next": "1"}));
+    }
+
+    #[test]
+    fn conflicting_keys_are_errors() {
+        let err = parse("a=1\n\na.b=2\n").expect_err("conflicting keys");
+        assert_eq!(err.line, Some(3));
+    }
+}
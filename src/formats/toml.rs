@@ -0,0 +1,95 @@
+use serde_json::{Map, Value};
+use toml::value::Datetime;
+use toml::Value as Toml;
+
+use crate::error::VarsError;
+
+/// Parses a TOML document.
+///
+/// Datetimes become RFC 3339 strings so they can be used with `date_format`: local datetimes are
+/// taken as UTC and local dates as midnight UTC. Local times are kept as `HH:MM:SS` strings.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let table = contents.parse::<toml::Table>().map_err(|e| {
+        let error = VarsError::new(e.message().trim_end());
+        match e.span() {
+            Some(span) => error.at_offset(contents, span.start),
+            None => error,
+        }
+    })?;
+    Ok(to_json(Toml::Table(table)))
+}
+
+fn to_json(value: Toml) -> Value {
+    match value {
+        Toml::String(s) => Value::String(s),
+        Toml::Integer(n) => Value::from(n),
+        // TOML allows nan and inf, which JSON can't represent
+        Toml::Float(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or_else(|| Value::String(f.to_string())),
+        Toml::Boolean(b) => Value::Bool(b),
+        Toml::Datetime(datetime) => Value::String(datetime_to_string(datetime)),
+        Toml::Array(items) => Value::Array(items.into_iter().map(to_json).collect()),
+        Toml::Table(table) => Value::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, to_json(value)))
+                .collect::<Map<_, _>>(),
+        ),
+    }
+}
+
+fn datetime_to_string(datetime: Datetime) -> String {
+    match (datetime.date, datetime.time, datetime.offset) {
+        (Some(_), Some(_), Some(_)) | (None, Some(_), None) => datetime.to_string(),
+        (Some(date), Some(time), None) => format!("{date}T{time}Z"),
+        (Some(date), None, _) => format!("{date}T00:00:00Z"),
+        // not a valid TOML datetime, but the type allows it
+        _ => datetime.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn tables_and_arrays() {
+        let value = parse(
+            "[package]\nname = \"templatier\"\nversion = \"0.2.0\"\n\n[[bin]]\nname = \"a\"\n\n[[bin]]\nname = \"b\"\n",
+        )
+        .expect("valid toml");
+        assert_eq!(
+            value,
+            json!({
+                "package": {"name": "templatier", "version": "0.2.0"},
+                "bin": [{"name": "a"}, {"name": "b"}],
+            })
+        );
+    }
+
+    #[test]
+    fn datetimes_become_rfc3339_strings() {
+        let value = parse(
+            "odt = 1979-05-27T07:32:00-07:00\nldt = 1979-05-27T07:32:00\nld = 1979-05-27\nlt = 07:32:00\n",
+        )
+        .expect("valid toml");
+        assert_eq!(
+            value,
+            json!({
+                "odt": "1979-05-27T07:32:00-07:00",
+                "ldt": "1979-05-27T07:32:00Z",
+                "ld": "1979-05-27T00:00:00Z",
+                "lt": "07:32:00",
+            })
+        );
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("a = 1\nb = [1, 2\n").expect_err("invalid toml");
+        assert_eq!(err.line, Some(3));
+    }
+}
@@ -0,0 +1,133 @@
+use roxmltree::{Document, Node, ParsingOptions};
+use serde_json::{Map, Value};
+
+use crate::error::VarsError;
+
+/// Prefix of the keys that hold attributes.
+const ATTRIBUTE_PREFIX: &str = "@";
+/// Key that holds the text of elements that also have attributes or children.
+const TEXT_KEY: &str = "#text";
+
+impl From<roxmltree::Error> for VarsError {
+    fn from(e: roxmltree::Error) -> Self {
+        let pos = e.pos();
+        VarsError::new(e.to_string()).at(pos.row as usize, pos.col as usize)
+    }
+}
+
+/// Parses an XML document into `{"<root element>": <element>}`, where every element becomes:
+///
+/// - a string with its (trimmed) text when it has neither attributes nor child elements,
+/// - otherwise an object with its attributes under `@name` keys, its child elements under their
+///   names and its text, if there's any, under `#text`.
+///
+/// Child elements appearing more than once become an array (in document order). Namespace
+/// prefixes are dropped, only local names are used. A `<!DOCTYPE>` is accepted, and the entities
+/// it declares are expanded.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let document = Document::parse_with_options(contents, options)?;
+    let root = document.root_element();
+    let mut map = Map::new();
+    map.insert(root.tag_name().name().to_owned(), element(root));
+    Ok(Value::Object(map))
+}
+
+fn element(node: Node) -> Value {
+    let text = node
+        .children()
+        .filter(|child| child.is_text())
+        .filter_map(|child| child.text())
+        .collect::<String>();
+    let text = text.trim();
+
+    let has_children = node.children().any(|child| child.is_element());
+    if node.attributes().len() == 0 && !has_children {
+        return Value::String(text.to_owned());
+    }
+
+    let mut map = Map::new();
+    for attribute in node.attributes() {
+        map.insert(
+            format!("{ATTRIBUTE_PREFIX}{}", attribute.name()),
+            Value::String(attribute.value().to_owned()),
+        );
+    }
+    for child in node.children().filter(|child| child.is_element()) {
+        let name = child.tag_name().name().to_owned();
+        let value = element(child);
+        match map.get_mut(&name) {
+            Some(Value::Array(items)) => items.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                map.insert(name, value);
+            }
+        }
+    }
+    if !text.is_empty() {
+        map.insert(TEXT_KEY.to_owned(), Value::String(text.to_owned()));
+    }
+    Value::Object(map)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn elements_attributes_and_repetitions() {
+        let value = parse(
+            r#"<?xml version="1.0"?>
+            <inventory xmlns:x="urn:x" region="eu">
+                <host name="web1" x:role="web">10.0.0.1</host>
+                <host name="web2">10.0.0.2</host>
+                <owner>ops</owner>
+                <notes><![CDATA[a < b]]></notes>
+                <empty/>
+            </inventory>"#,
+        )
+        .expect("valid xml");
+        assert_eq!(
+            value,
+            json!({
+                "inventory": {
+                    "@region": "eu",
+                    "host": [
+                        {"@name": "web1", "@role": "web", "#text": "10.0.0.1"},
+                        {"@name": "web2", "#text": "10.0.0.2"},
+                    ],
+                    "owner": "ops",
+                    "notes": "a < b",
+                    "empty": "",
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn doctypes_are_accepted() {
+        let value = parse(
+            r#"<?xml version="1.0"?>
+<!DOCTYPE note SYSTEM "note.dtd" [
+  <!ENTITY sender "Ana">
+]>
+<note><from>&sender;</from></note>"#,
+        )
+        .expect("valid xml");
+        assert_eq!(value, json!({"note": {"from": "Ana"}}));
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("<a>\n  <b>\n</a>").expect_err("invalid xml");
+        assert_eq!(err.line, Some(3));
+    }
+}
@@ -0,0 +1,107 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use serde_yaml_ng::Value as Yaml;
+
+use crate::error::VarsError;
+
+impl From<serde_yaml_ng::Error> for VarsError {
+    fn from(e: serde_yaml_ng::Error) -> Self {
+        let error = VarsError::new(e.to_string());
+        match e.location() {
+            Some(location) => error.at(location.line(), location.column()),
+            None => error,
+        }
+    }
+}
+
+/// Parses a YAML stream. A single document is returned as is, several documents are returned as
+/// an array and an empty stream as an empty object.
+pub fn parse(contents: &str) -> Result<Value, VarsError> {
+    let mut documents = Vec::new();
+    for document in serde_yaml_ng::Deserializer::from_str(contents) {
+        documents.push(to_json(Yaml::deserialize(document)?)?);
+    }
+    match documents.len() {
+        0 => Ok(Value::Object(Map::new())),
+        1 => match documents.pop() {
+            Some(Value::Null) | None => Ok(Value::Object(Map::new())),
+            Some(document) => Ok(document),
+        },
+        _ => Ok(Value::Array(documents)),
+    }
+}
+
+fn to_json(value: Yaml) -> Result<Value, VarsError> {
+    Ok(match value {
+        Yaml::Null => Value::Null,
+        Yaml::Bool(b) => Value::Bool(b),
+        Yaml::Number(n) => {
+            if let Some(n) = n.as_i64() {
+                Value::from(n)
+            } else if let Some(n) = n.as_u64() {
+                Value::from(n)
+            } else {
+                let f = n.as_f64().unwrap_or(f64::NAN);
+                serde_json::Number::from_f64(f)
+                    .map(Value::Number)
+                    .ok_or_else(|| VarsError::new(format!("{n} can't be represented in JSON")))?
+            }
+        }
+        Yaml::String(s) => Value::String(s),
+        Yaml::Sequence(items) => {
+            Value::Array(items.into_iter().map(to_json).collect::<Result<_, _>>()?)
+        }
+        Yaml::Mapping(mapping) => {
+            let mut map = Map::new();
+            for (key, value) in mapping {
+                map.insert(key_to_string(key)?, to_json(value)?);
+            }
+            Value::Object(map)
+        }
+        // tags (`!something`) don't mean anything for templating, keep just the value
+        Yaml::Tagged(tagged) => to_json(tagged.value)?,
+    })
+}
+
+/// JSON only allows string keys, so scalar keys are turned into their string representation.
+fn key_to_string(key: Yaml) -> Result<String, VarsError> {
+    match key {
+        Yaml::String(s) => Ok(s),
+        Yaml::Bool(b) => Ok(b.to_string()),
+        Yaml::Number(n) => Ok(n.to_string()),
+        Yaml::Null => Ok("null".to_owned()),
+        Yaml::Tagged(tagged) => key_to_string(tagged.value),
+        Yaml::Sequence(_) | Yaml::Mapping(_) => Err(VarsError::new(
+            "mappings and sequences can't be used as keys",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::parse;
+
+    #[test]
+    fn single_document() {
+        let value = parse("name: app\nports:\n  - 80\n  - 443\n1: one\n").expect("valid yaml");
+        assert_eq!(
+            value,
+            json!({"name": "app", "ports": [80, 443], "1": "one"})
+        );
+    }
+
+    #[test]
+    fn multiple_documents_become_an_array() {
+        let value = parse("---\nname: a\n---\nname: b\n").expect("valid yaml");
+        assert_eq!(value, json!([{"name": "a"}, {"name": "b"}]));
+    }
+
+    #[test]
+    fn errors_have_a_position() {
+        let err = parse("a: 1\nb: [1, 2\n").expect_err("invalid yaml");
+        assert!(err.line.is_some());
+        assert!(err.column.is_some());
+    }
+}
@@ -0,0 +1,27 @@
+use std::fs;
+use std::io::{self, Read};
+
+use crate::error::AppError;
+
+/// Path that stands for stdin on the command line.
+pub const STDIN: &str = "-";
+
+/// Reads `path` into a string, or the whole stdin when `path` is `-`.
+pub fn read_to_string(path: &str) -> Result<String, AppError> {
+    let result = if path == STDIN {
+        let mut buf = String::new();
+        io::stdin().read_to_string(&mut buf).map(|_| buf)
+    } else {
+        fs::read_to_string(path)
+    };
+    result.map_err(|e| AppError::io(display_name(path), e))
+}
+
+/// Name used to refer to `path` in diagnostics.
+pub fn display_name(path: &str) -> &str {
+    if path == STDIN {
+        "<stdin>"
+    } else {
+        path
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::HashMap;
+
+use handlebars::template::{DecoratorTemplate, Parameter, TemplateElement, TemplateMapping};
+use handlebars::{Handlebars, Template};
+
+use crate::error::AppError;
+
+/// Directory of the partials directories where layouts are also looked up.
+const LAYOUTS_DIR: &str = "layouts";
+
+/// Layout declared by `template` in a `{{!< name}}` comment at its very beginning.
+pub fn declared(template: &str) -> Option<&str> {
+    let rest = template.trim_start().strip_prefix("{{!<")?;
+    let (name, _) = rest.split_once("}}")?;
+    Some(name.trim()).filter(|name| !name.is_empty())
+}
+
+/// Name of the registered partial to use as the layout `name`: `name` itself or, failing that,
+/// `layouts/name`. `path` is the template asking for it.
+pub fn resolve(handlebars: &Handlebars, name: &str, path: &str) -> Result<String, AppError> {
+    let nested = format!("{LAYOUTS_DIR}/{name}");
+    [name.to_owned(), nested]
+        .into_iter()
+        .find(|candidate| handlebars.get_template(candidate).is_some())
+        .ok_or_else(|| AppError::UnknownLayout {
+            name: name.to_owned(),
+            path: path.to_owned(),
+        })
+}
+
+/// Turns `template` into a partial block of `layout`, so its inline partials
+/// (`{{#*inline "content"}}`) fill the layout's `{{> content}}`/`{{#> content}}` slots and the
+/// rest of it is the layout's `{{> @partial-block}}`.
+///
+/// The compiled template is wrapped as is, so errors raised in it keep its name and positions
+/// instead of being attributed to the layout.
+pub fn wrap(template: &Template, layout: &str) -> Template {
+    let block = DecoratorTemplate {
+        name: Parameter::Name(layout.to_owned()),
+        params: Vec::new(),
+        hash: HashMap::new(),
+        template: Some(template.clone()),
+        indent: None,
+    };
+    Template {
+        name: template.name.clone(),
+        elements: vec![TemplateElement::PartialBlock(Box::new(block))],
+        mapping: vec![TemplateMapping(1, 1)],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{declared, resolve, wrap};
+    use crate::{build_hb_registry, TPLT};
+
+    #[test]
+    fn declarations() {
+        assert_eq!(declared("{{!< layouts/base}}\nbody"), Some("layouts/base"));
+        assert_eq!(declared("\n  {{!<base }}"), Some("base"));
+        assert_eq!(declared("body {{!< base}}"), None);
+        assert_eq!(declared("{{! comment}}"), None);
+    }
+
+    #[test]
+    fn templates_override_layout_blocks() {
+        let page = "{{!< base}}\n{{#*inline \"title\"}}{{name}}{{/inline}}Hello {{name}}";
+        let mut hb = build_hb_registry(page, false).expect("couldn't build template");
+        hb.register_partial(
+            "layouts/base",
+            "<title>{{#> title}}Untitled{{/title}}</title>{{#> footer}}(c){{/footer}}<body>{{> @partial-block}}</body>",
+        )
+        .unwrap();
+
+        let layout = resolve(&hb, declared(page).unwrap(), "page.hbs").expect("layout exists");
+        assert_eq!(layout, "layouts/base");
+        let wrapped = wrap(hb.get_template(TPLT).unwrap(), &layout);
+        hb.register_template(TPLT, wrapped);
+        assert_eq!(
+            hb.render(TPLT, &json!({"name": "Ana"})).unwrap(),
+            "<title>Ana</title>(c)<body>Hello Ana</body>"
+        );
+
+        assert!(resolve(&hb, "missing", "page.hbs").is_err());
+    }
+
+    #[test]
+    fn errors_keep_the_template_they_were_raised_in() {
+        let page = "{{!< base}}\nA\n  {{add \"x\" 1}}";
+        let mut hb = build_hb_registry(page, false).unwrap();
+        hb.register_partial(
+            "base",
+            "<main>\n{{> @partial-block}}\n</main>{{add \"y\" 2}}",
+        )
+        .unwrap();
+        let wrapped = wrap(hb.get_template(TPLT).unwrap(), "base");
+        hb.register_template(TPLT, wrapped);
+
+        let err = hb.render(TPLT, &json!({})).expect_err("the page fails");
+        assert_eq!(err.template_name.as_deref(), Some(TPLT));
+        assert_eq!((err.line_no, err.column_no), (Some(3), Some(3)));
+
+        hb.register_template_string("fine", "{{!< base}}\nfine")
+            .unwrap();
+        let wrapped = wrap(hb.get_template("fine").unwrap(), "base");
+        hb.register_template("fine", wrapped);
+        let err = hb.render("fine", &json!({})).expect_err("the layout fails");
+        assert_eq!(err.template_name.as_deref(), Some("base"));
+        assert_eq!((err.line_no, err.column_no), (Some(3), Some(8)));
+    }
+}
@@ -1,29 +1,169 @@
-use clap::Parser;
-use handlebars::{handlebars_helper, Handlebars, JsonTruthy};
-use serde_json::Value;
-use std::fs;
+use clap::{error::ErrorKind, ArgGroup, CommandFactory, Parser};
+use handlebars::{
+    handlebars_helper, BlockContext, Context, Handlebars, JsonTruthy, RenderContext, RenderError,
+    Renderable, StringOutput,
+};
+use serde_json::{json, Value};
+use std::io::{self, BufWriter, StdoutLock, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
 use crate::customhelper::{IsDefined, IsDefinedPass, IsUndefined};
+use crate::diagnostics::{Diagnostic, DiagnosticsFormat};
+use crate::error::AppError;
+use crate::formats::{FormatOptions, VarsFormat};
+use crate::matrix::Axes;
+use crate::overrides::Assignment;
+use crate::templates::{Entry, Named, Source, Sources, Templates};
+use crate::vars::{ArrayMerge, Segment, VarPath, Vars};
 
 mod customhelper;
+mod diagnostics;
+mod error;
+mod formats;
+mod input;
+mod layout;
+mod matrix;
+mod ndjson;
+mod output;
+mod overrides;
+mod partials;
 mod sprig;
+mod templates;
+mod tree;
+mod vars;
 
-/// Comando que permite aplicar variables de un archivo JSON a una plantilla de handlebars (.hbs).
+/// Comando que permite aplicar variables de archivos JSON, YAML u otros formatos a una plantilla de
+/// handlebars (.hbs).
 #[derive(Parser)]
 #[command(
     author,
     version,
     about,
     long_about = None,
+    group(ArgGroup::new("batch").args(["ndjson", "for_each", "matrix"])),
 )]
 struct AppArgs {
-    /// Archivo de plantilla
-    template: String,
-    /// Archivo de variables
-    vars: String,
+    /// Archivo de plantilla (`-` para leerla desde stdin) o directorio de plantillas
+    #[arg(required_unless_present_any = ["expr", "templates"])]
+    template: Option<String>,
+    /// Archivo de variables (`-` para leerlas desde stdin). Si se omite se usa `{}`
+    vars: Option<String>,
+    /// Extra vars file, merged on top of the previous ones. Can be repeated
+    #[arg(long = "vars", value_name = "FILE")]
+    extra_vars: Vec<String>,
+    /// Extra `.env` file, merged on top of the vars files. Can be repeated
+    #[arg(long, value_name = "FILE")]
+    dotenv: Vec<String>,
+    /// Format of the vars files. By default it's guessed from their extension, falling back to
+    /// JSON
+    #[arg(long, value_enum)]
+    vars_format: Option<VarsFormat>,
+    /// Key under which the rows of CSV/TSV vars files are exposed
+    #[arg(long, value_name = "KEY", default_value = "rows")]
+    csv_root: String,
+    /// Gives CSV/TSV cells that look like numbers or booleans that type, and turns empty cells into
+    /// null
+    #[arg(long)]
+    infer_types: bool,
+    /// How arrays defined in more than one vars file are merged
+    #[arg(long, value_enum, default_value_t = ArrayMerge::Replace)]
+    array_merge: ArrayMerge,
+    /// Sets a value after loading the vars files (e.g. `servers[2].port=8080`). `true`, `false`,
+    /// `null` and integers keep their type, anything else is a string. Can be repeated
+    #[arg(long, value_name = "PATH=VALUE", value_parser = overrides::parse_set)]
+    set: Vec<Assignment>,
+    /// Like --set, but the value is always a string
+    #[arg(long, value_name = "PATH=VALUE", value_parser = overrides::parse_set_string)]
+    set_string: Vec<Assignment>,
+    /// Like --set, but the value is parsed as JSON
+    #[arg(long, value_name = "PATH=JSON", value_parser = overrides::parse_set_json)]
+    set_json: Vec<Assignment>,
+    /// Exposes the process environment variables under the `env` key
+    #[arg(long)]
+    env: bool,
+    /// Only exposes the environment variables whose name starts with this prefix (implies --env)
+    #[arg(long, value_name = "PREFIX")]
+    env_prefix: Option<String>,
+    /// Reports on stderr which vars file each value under this path came from. Can be repeated
+    #[arg(long, value_name = "PATH")]
+    explain_var: Vec<VarPath>,
+    /// Uses this string as the template instead of reading it from a file. The first positional
+    /// argument is then taken as the vars file
+    #[arg(short, long)]
+    expr: Option<String>,
+    /// Reads the vars file (stdin if it's omitted) as newline-delimited JSON and renders the
+    /// template once per record, merged on top of the other vars. Records are streamed one at a
+    /// time
+    #[arg(long, conflicts_with = "output")]
+    ndjson: bool,
+    /// Renders the template once for every element of the array at this path, with the element
+    /// as context (`@root` is still the whole vars)
+    #[arg(long, value_name = "PATH", conflicts_with = "output")]
+    for_each: Option<VarPath>,
+    /// Renders the template once for every combination of the axes declared at this path (an
+    /// object of arrays, `matrix` by default). While rendering, the path holds the current
+    /// combination (e.g. `{{matrix.env}}`)
+    #[arg(
+        long,
+        value_name = "PATH",
+        num_args = 0..=1,
+        default_missing_value = "matrix",
+        require_equals = true,
+        conflicts_with = "output"
+    )]
+    matrix: Option<VarPath>,
+    /// Written between consecutive outputs of --ndjson, --for-each and --matrix. The `\n`, `\r`,
+    /// `\t`, `\0` and `\\` escapes are understood
+    #[arg(
+        long,
+        value_parser = output::parse_delimiter,
+        default_value = "",
+        hide_default_value = true,
+        requires = "batch",
+        conflicts_with = "output_pattern"
+    )]
+    delimiter: String,
+    /// Registers the template at PATH as NAME, to be rendered with --entry or included from the
+    /// others as `{{> NAME}}`. The positional template, if any, is named `template`. Can be
+    /// repeated
+    #[arg(long = "template", value_name = "NAME=PATH", value_parser = templates::parse_named)]
+    templates: Vec<Named>,
+    /// Template to render, by default the positional one or the only --template. With `=PATH` its
+    /// output goes to that file instead of stdout or --output. Can be repeated to render several
+    /// templates with the same vars
+    #[arg(long, value_name = "NAME[=PATH]", value_parser = templates::parse_entry)]
+    entry: Vec<Entry>,
+    /// Writes the result to this file (atomically) instead of stdout
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Writes the output of every --ndjson record, --for-each element or --matrix combination to
+    /// its own file, named by rendering this template with it (e.g. `out/{{id}}.txt`)
+    #[arg(long, value_name = "TEMPLATE", requires = "batch")]
+    output_pattern: Option<String>,
+    /// Registers every file in this directory as a partial, named by its path relative to it
+    /// without the extension (`{{> layouts/base}}`). Can be repeated
+    #[arg(long, value_name = "DIR")]
+    partials: Vec<String>,
+    /// Partial used as layout of the template (or of every template of a template directory),
+    /// unless it declares its own with `{{!< name}}`. Looked up as NAME and then as layouts/NAME
+    /// among the partials
+    #[arg(long, value_name = "NAME")]
+    layout: Option<String>,
+    /// Leaves out the files matching this glob when the template is a directory, on top of the
+    /// ones listed in its `.templatierignore`. Can be repeated
+    #[arg(long, value_name = "GLOB")]
+    ignore: Vec<String>,
+    /// Permissions of the output files, in octal (e.g. 644). By default the ones of the files being
+    /// replaced are kept
+    #[arg(long, value_parser = output::parse_mode)]
+    mode: Option<u32>,
     /// Uses handlebars' strict mode
     #[arg(long)]
     strict: bool,
+    /// Format used to report errors on stderr
+    #[arg(long, value_enum, default_value_t = DiagnosticsFormat::Human)]
+    diagnostics: DiagnosticsFormat,
 }
 
 // trait Booly {
@@ -40,38 +180,533 @@ struct AppArgs {
 //     a.value() || b.value()
 // }
 
+/// Name of the positional (or `--expr`) template.
 const TPLT: &str = "template";
 
-fn main() -> anyhow::Result<()> {
-    let args = AppArgs::parse();
-    let template =
-        fs::read_to_string(&args.template).expect("template file not found / couldn't be opened");
-    let vars = fs::read_to_string(&args.vars).expect("vars file not found / couldn't be opened");
+/// Name under which `--output-pattern` is registered.
+const OUTPUT_PATTERN: &str = "output-pattern";
+
+/// Key under which `--env` exposes the environment variables.
+const ENV_KEY: &str = "env";
+
+/// Name given to `--expr` templates in diagnostics.
+const EXPR_NAME: &str = "<expr>";
+
+impl AppArgs {
+    /// With `--expr` there's no template file, so the only positional argument is the vars file.
+    fn normalize(mut self) -> Result<Self, clap::Error> {
+        if self.expr.is_some() {
+            if self.vars.is_some() {
+                return Err(AppArgs::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "a template file can't be given together with --expr",
+                ));
+            }
+            self.vars = self.template.take();
+        }
+        if self.ndjson && self.vars.is_none() {
+            self.vars = Some(input::STDIN.to_owned());
+        }
+        let stdin_inputs = self
+            .template
+            .iter()
+            .chain(&self.vars)
+            .chain(&self.extra_vars)
+            .chain(&self.dotenv)
+            .chain(self.templates.iter().map(|named| &named.path))
+            .filter(|path| *path == input::STDIN)
+            .count();
+        if self.template_dir().is_some() {
+            if self.output.is_none() {
+                return Err(AppArgs::command().error(
+                    ErrorKind::MissingRequiredArgument,
+                    "a template directory is rendered into the directory given with --output",
+                ));
+            }
+            if self.ndjson || self.for_each.is_some() || self.matrix.is_some() {
+                return Err(AppArgs::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "--ndjson, --for-each and --matrix can't be used with a template directory",
+                ));
+            }
+            if !self.templates.is_empty() || !self.entry.is_empty() {
+                return Err(AppArgs::command().error(
+                    ErrorKind::ArgumentConflict,
+                    "--template and --entry can't be used with a template directory",
+                ));
+            }
+        } else {
+            self.check_entries()?;
+        }
+        if stdin_inputs > 1 {
+            return Err(AppArgs::command().error(
+                ErrorKind::ArgumentConflict,
+                "only one of the template and the vars files can be read from stdin",
+            ));
+        }
+        Ok(self)
+    }
 
-    let handlebars: Handlebars = build_hb_registry(&template, args.strict)?;
+    /// Checks the `--template` names and the `--entry` ones, which default to the positional
+    /// template or, failing that, the only `--template`.
+    fn check_entries(&mut self) -> Result<(), clap::Error> {
+        let main = (self.expr.is_some() || self.template.is_some()).then_some(TPLT);
+        let mut names: Vec<String> = main.iter().map(|name| name.to_string()).collect();
+        for named in &self.templates {
+            if names.contains(&named.name) || named.name == OUTPUT_PATTERN {
+                return Err(AppArgs::command().error(
+                    ErrorKind::ArgumentConflict,
+                    format!("there's already a template named `{}`", named.name),
+                ));
+            }
+            names.push(named.name.clone());
+        }
 
-    let vars = serde_json::from_str::<serde_json::Value>(&vars)?;
-    match handlebars.render(TPLT, &vars) {
-        Ok(out) => {
-            println!("{out}")
+        if self.entry.is_empty() {
+            let name = match (main, names.as_slice()) {
+                (Some(main), _) => main.to_owned(),
+                (None, [only]) => only.clone(),
+                (None, _) => {
+                    return Err(AppArgs::command().error(
+                        ErrorKind::MissingRequiredArgument,
+                        "choose which of the --template ones to render with --entry",
+                    ))
+                }
+            };
+            self.entry.push(Entry { name, output: None });
         }
+        if let Some(entry) = self.entry.iter().find(|entry| !names.contains(&entry.name)) {
+            return Err(AppArgs::command().error(
+                ErrorKind::InvalidValue,
+                format!("--entry: there's no template named `{}`", entry.name),
+            ));
+        }
+        if self
+            .entry
+            .iter()
+            .filter(|entry| entry.output.is_none())
+            .count()
+            > 1
+        {
+            return Err(AppArgs::command().error(
+                ErrorKind::ArgumentConflict,
+                "only one --entry can be written to stdout or --output, give the others a file with --entry NAME=PATH",
+            ));
+        }
+        let batch = self.ndjson || self.for_each.is_some() || self.matrix.is_some();
+        if batch && (self.entry.len() > 1 || self.entry[0].output.is_some()) {
+            return Err(AppArgs::command().error(
+                ErrorKind::ArgumentConflict,
+                "--ndjson, --for-each and --matrix render a single --entry, written to stdout or --output-pattern",
+            ));
+        }
+        let writes_files = self.output.is_some()
+            || self.output_pattern.is_some()
+            || self.entry.iter().any(|entry| entry.output.is_some());
+        if self.mode.is_some() && !writes_files {
+            return Err(AppArgs::command().error(
+                ErrorKind::MissingRequiredArgument,
+                "--mode needs a file to apply to: --output, --output-pattern or --entry NAME=PATH",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Vars files in the order they're layered. With `--ndjson` the positional one is the stream
+    /// of records instead.
+    fn vars_files(&self) -> impl Iterator<Item = &String> {
+        self.vars
+            .iter()
+            .filter(|_| !self.ndjson)
+            .chain(&self.extra_vars)
+    }
+
+    /// The template, when it's a directory.
+    fn template_dir(&self) -> Option<&Path> {
+        self.template
+            .as_deref()
+            .map(Path::new)
+            .filter(|path| path.is_dir())
+    }
+
+    /// The positional template, or `--expr`, registered as [`TPLT`].
+    fn read_template(&self) -> Result<Option<Source>, AppError> {
+        let (path, contents) = match (&self.expr, &self.template) {
+            (Some(expr), _) => (EXPR_NAME, expr.clone()),
+            (None, Some(path)) => (input::display_name(path), input::read_to_string(path)?),
+            (None, None) => return Ok(None),
+        };
+        Ok(Some(Source {
+            name: TPLT.to_owned(),
+            path: path.to_owned(),
+            contents,
+        }))
+    }
+}
+
+fn main() -> ExitCode {
+    let args = AppArgs::parse().normalize().unwrap_or_else(|e| e.exit());
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
         Err(e) => {
-            println!("{}", e);
+            Diagnostic::from(&e).emit(args.diagnostics);
+            e.exit_code()
+        }
+    }
+}
+
+fn run(args: &AppArgs) -> Result<(), AppError> {
+    if let Some(dir) = args.template_dir() {
+        let mut handlebars = new_hb_registry(args.strict);
+        let mut sources = Sources::default();
+        partials::register(&mut handlebars, &args.partials, &mut sources)?;
+        let vars = load_vars(args)?;
+        let options = tree::Options {
+            ignore: tree::Ignore::load(dir, &args.ignore)?,
+            layout: args.layout.clone(),
+            mode: args.mode,
+        };
+        let target = args.output.as_deref().expect("normalize requires --output");
+        return tree::render(
+            dir,
+            Path::new(target),
+            &mut handlebars,
+            sources,
+            vars,
+            &options,
+        );
+    }
+
+    let templates = Templates::read(args.read_template()?, &args.templates)?;
+
+    let mut handlebars = new_hb_registry(args.strict);
+    let mut sources = Sources::default();
+    templates.register(&mut handlebars, &mut sources)?;
+    partials::register(&mut handlebars, &args.partials, &mut sources)?;
+    templates.apply_layouts(&mut handlebars, args.layout.as_deref(), &args.entry)?;
+    if let Some(pattern) = &args.output_pattern {
+        handlebars
+            .register_template_string(OUTPUT_PATTERN, pattern)
+            .map_err(|source| AppError::Template {
+                path: "--output-pattern".to_owned(),
+                contents: pattern.clone(),
+                source: Box::new(source),
+            })?;
+        sources.insert(OUTPUT_PATTERN, "--output-pattern", pattern);
+    }
+
+    let vars = load_vars(args)?;
+    // normalize leaves a single entry in batch modes
+    let template = templates.get(&args.entry[0].name);
+    if args.ndjson {
+        return render_records(args, &handlebars, &sources, &vars, template);
+    }
+    if let Some(path) = &args.for_each {
+        return render_each(args, &handlebars, &sources, vars, path, template);
+    }
+    if let Some(path) = &args.matrix {
+        return render_matrix(args, &handlebars, &sources, vars, path, template);
+    }
+
+    for entry in &args.entry {
+        let template = templates.get(&entry.name);
+        let out = handlebars
+            .render(&template.name, &vars)
+            .map_err(|e| sources.render_error(&template.path, e))?;
+        match entry.output.as_ref().or(args.output.as_ref()) {
+            Some(path) => {
+                output::write_atomic(Path::new(path), out.as_bytes(), args.mode)
+                    .map_err(|e| AppError::io(path, e))?;
+            }
+            None => io::stdout()
+                .write_all(out.as_bytes())
+                .map_err(|e| AppError::io("<stdout>", e))?,
         }
     }
     Ok(())
 }
 
-fn build_hb_registry(template: &str, strict_mode: bool) -> anyhow::Result<Handlebars> {
-    let mut handlebars = Handlebars::new();
+/// Layers the vars files, `.env` files, environment and `--set` flags, in that order.
+fn load_vars(args: &AppArgs) -> Result<Value, AppError> {
+    let options = FormatOptions {
+        csv_root: args.csv_root.clone(),
+        infer_types: args.infer_types,
+    };
+    let mut vars = if args.explain_var.is_empty() {
+        Vars::default()
+    } else {
+        Vars::with_origins()
+    };
+    for path in args.vars_files() {
+        let layer = vars::load_file(path, args.vars_format, &options)?;
+        vars.add_layer(input::display_name(path), layer, args.array_merge);
+    }
+    for path in &args.dotenv {
+        let layer = vars::load_file(path, Some(VarsFormat::Dotenv), &options)?;
+        vars.add_layer(input::display_name(path), layer, args.array_merge);
+    }
+    if args.env || args.env_prefix.is_some() {
+        let environment = vars::environment(args.env_prefix.as_deref().unwrap_or_default());
+        vars.add_layer(
+            "environment",
+            serde_json::json!({ ENV_KEY: environment }),
+            args.array_merge,
+        );
+    }
+    // same order Helm uses
+    for assignment in args
+        .set_json
+        .iter()
+        .chain(&args.set)
+        .chain(&args.set_string)
+    {
+        vars.set(
+            assignment.source(),
+            &assignment.path,
+            assignment.value.clone(),
+        );
+    }
+    for path in &args.explain_var {
+        for origin in vars.origins(path) {
+            diagnostics::emit_origin(&origin, args.diagnostics);
+        }
+    }
+    Ok(vars.value)
+}
+
+/// Renders the template once per record of the `--ndjson` stream, each record merged on top of
+/// `base`.
+fn render_records(
+    args: &AppArgs,
+    handlebars: &Handlebars,
+    sources: &Sources,
+    base: &Value,
+    template: &Source,
+) -> Result<(), AppError> {
+    let stream = args.vars.as_deref().expect("normalize sets the stream");
+    let base_is_empty = base.as_object().is_some_and(|base| base.is_empty());
+    let mut batch = BatchOutput::new(args, handlebars);
+
+    for record in ndjson::open(stream)? {
+        let record = record?;
+        let context = if base_is_empty {
+            record
+        } else {
+            let mut context = base.clone();
+            vars::merge(&mut context, record, args.array_merge);
+            context
+        };
+        let out = handlebars
+            .render(&template.name, &context)
+            .map_err(|e| sources.render_error(&template.path, e))?;
+        let path = batch.path(sources, |names| names.render(OUTPUT_PATTERN, &context))?;
+        batch.write(&out, path.as_deref())?;
+    }
+    batch.finish()
+}
+
+/// Renders the template once for every element of the array at `path`, with the element as
+/// `this` and `@index`, `@first` and `@last` set like `{{#each}}` does.
+fn render_each(
+    args: &AppArgs,
+    handlebars: &Handlebars,
+    sources: &Sources,
+    vars: Value,
+    path: &VarPath,
+    template: &Source,
+) -> Result<(), AppError> {
+    let len = match vars::lookup(&vars, &path.0) {
+        Some(Value::Array(items)) => items.len(),
+        found => {
+            return Err(AppError::VarsShape {
+                option: "--for-each".to_owned(),
+                message: match found {
+                    Some(value) => format!("`{path}` is {}, not an array", vars::type_name(value)),
+                    None => format!("`{path}` isn't defined in the vars"),
+                },
+            })
+        }
+    };
+    let base_path: Vec<String> = path
+        .0
+        .iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => key.clone(),
+            Segment::Index(index) => index.to_string(),
+        })
+        .collect();
+    let context = Context::from(vars);
+    let mut batch = BatchOutput::new(args, handlebars);
+
+    for index in 0..len {
+        let mut element_path = base_path.clone();
+        element_path.push(index.to_string());
+        let locals = loop_locals(index, len);
+        let out = render_at(handlebars, &template.name, &context, &element_path, &locals)
+            .map_err(|e| sources.render_error(&template.path, e))?;
+        let path = batch.path(sources, |names| {
+            render_at(names, OUTPUT_PATTERN, &context, &element_path, &locals)
+        })?;
+        batch.write(&out, path.as_deref())?;
+    }
+    batch.finish()
+}
+
+/// Renders the template once for every combination of the matrix axes at `path`, replacing them
+/// with the current combination while rendering.
+fn render_matrix(
+    args: &AppArgs,
+    handlebars: &Handlebars,
+    sources: &Sources,
+    vars: Value,
+    path: &VarPath,
+    template: &Source,
+) -> Result<(), AppError> {
+    let axes = Axes::from_vars(&vars, path)?;
+    let count = axes.count();
+    let mut context = Context::from(vars);
+    let mut batch = BatchOutput::new(args, handlebars);
+
+    for index in 0..count {
+        *vars::lookup_mut(context.data_mut(), &path.0).expect("the axes were found there") =
+            axes.combination(index);
+        let locals = loop_locals(index, count);
+        let out = render_at(handlebars, &template.name, &context, &[], &locals)
+            .map_err(|e| sources.render_error(&template.path, e))?;
+        let path = batch.path(sources, |names| {
+            render_at(names, OUTPUT_PATTERN, &context, &[], &locals)
+        })?;
+        batch.write(&out, path.as_deref())?;
+    }
+    batch.finish()
+}
+
+/// `@index`, `@first` and `@last` for the `index`th of `len` renders.
+fn loop_locals(index: usize, len: usize) -> [(&'static str, Value); 3] {
+    [
+        ("index", json!(index)),
+        ("first", json!(index == 0)),
+        ("last", json!(index + 1 == len)),
+    ]
+}
+
+/// Renders the template registered as `name` with the value at `base_path` of `context` as
+/// `this`, the way block helpers do, so `@root` and `../` keep working.
+fn render_at(
+    handlebars: &Handlebars,
+    name: &str,
+    context: &Context,
+    base_path: &[String],
+    locals: &[(&str, Value)],
+) -> Result<String, RenderError> {
+    let template = handlebars
+        .get_template(name)
+        .expect("templates are registered before rendering");
+    let mut rc = RenderContext::new(template.name.as_ref());
+    // on top of the root block, like `{{#each}}` does, so `../` goes back to the root
+    let mut block = BlockContext::new();
+    *block.base_path_mut() = base_path.to_vec();
+    for (local, value) in locals {
+        block.set_local_var(local, value.clone());
+    }
+    rc.push_block(block);
+    let mut out = StringOutput::new();
+    template.render(handlebars, context, &mut rc, &mut out)?;
+    out.into_string().map_err(RenderError::from)
+}
+
+/// Where the outputs of `--ndjson` and `--for-each` go: stdout, separated by `--delimiter`, or
+/// the files named by `--output-pattern`.
+struct BatchOutput<'a> {
+    delimiter: &'a str,
+    mode: Option<u32>,
+    /// Renders `--output-pattern`, if given.
+    file_names: Option<Handlebars<'a>>,
+    stdout: BufWriter<StdoutLock<'static>>,
+    written: usize,
+}
+
+impl<'a> BatchOutput<'a> {
+    fn new(args: &'a AppArgs, handlebars: &Handlebars<'a>) -> Self {
+        Self {
+            delimiter: &args.delimiter,
+            mode: args.mode,
+            file_names: args.output_pattern.as_ref().map(|_| unescaped(handlebars)),
+            stdout: BufWriter::new(io::stdout().lock()),
+            written: 0,
+        }
+    }
+
+    /// The file the current output goes to when there's an `--output-pattern`, rendered by
+    /// `render`.
+    fn path(
+        &self,
+        sources: &Sources,
+        render: impl FnOnce(&Handlebars) -> Result<String, RenderError>,
+    ) -> Result<Option<String>, AppError> {
+        self.file_names
+            .as_ref()
+            .map(render)
+            .transpose()
+            .map_err(|e| sources.render_error("--output-pattern", e))
+    }
+
+    /// Writes `out` to `path`, or to stdout when there's no path.
+    fn write(&mut self, out: &str, path: Option<&str>) -> Result<(), AppError> {
+        match path {
+            Some(path) => {
+                output::write_atomic(Path::new(path), out.as_bytes(), self.mode)
+                    .map_err(|e| AppError::io(path, e))?;
+            }
+            None => {
+                if self.written > 0 {
+                    self.stdout
+                        .write_all(self.delimiter.as_bytes())
+                        .map_err(|e| AppError::io("<stdout>", e))?;
+                }
+                self.stdout
+                    .write_all(out.as_bytes())
+                    .map_err(|e| AppError::io("<stdout>", e))?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
 
+    fn finish(mut self) -> Result<(), AppError> {
+        self.stdout.flush().map_err(|e| AppError::io("<stdout>", e))
+    }
+}
+
+/// A registry with all the helpers and `template` registered as [`TPLT`].
+#[cfg(test)]
+fn build_hb_registry(
+    template: &str,
+    strict_mode: bool,
+) -> Result<Handlebars<'static>, handlebars::TemplateError> {
+    let mut handlebars = new_hb_registry(strict_mode);
     handlebars.register_template_string(TPLT, template)?;
+    Ok(handlebars)
+}
+
+/// A copy of `handlebars` that doesn't HTML-escape, for rendering file names: `{{name}}` with
+/// `tom&jerry` should give `tom&jerry`, not `tom&amp;jerry`.
+fn unescaped<'reg>(handlebars: &Handlebars<'reg>) -> Handlebars<'reg> {
+    let mut unescaped = handlebars.clone();
+    unescaped.register_escape_fn(handlebars::no_escape);
+    unescaped
+}
+
+/// A registry with all the helpers but no templates.
+fn new_hb_registry(strict_mode: bool) -> Handlebars<'static> {
+    let mut handlebars = Handlebars::new();
     handlebars.set_strict_mode(strict_mode);
 
     // add sprig helpers
     sprig::add_math_helpers(&mut handlebars);
     sprig::add_str_helpers(&mut handlebars);
     sprig::add_date_helpers(&mut handlebars);
+    sprig::add_os_helpers(&mut handlebars);
 
     // add my extra helpers
     handlebars_helper!(or:|a: Value, b: Value, {include_zero: bool = false}| { a.is_truthy(include_zero) || b.is_truthy(include_zero) });
@@ -88,7 +723,7 @@ fn build_hb_registry(template: &str, strict_mode: bool) -> anyhow::Result<Handle
     handlebars.register_helper("isdef_pass", Box::new(IsDefinedPass));
     handlebars.register_helper("isundef", Box::new(IsUndefined));
 
-    Ok(handlebars)
+    handlebars
 }
 
 #[cfg(test)]
@@ -96,7 +731,10 @@ mod tests {
     const TPLT: &str = super::TPLT;
     use serde_json::json;
 
-    use crate::build_hb_registry;
+    use clap::error::ErrorKind;
+    use clap::Parser;
+
+    use crate::{build_hb_registry, load_vars, render_at, unescaped, AppArgs};
 
     #[test]
     fn simple_template() {
@@ -110,6 +748,12 @@ mod tests {
         );
     }
 
+    #[test]
+    fn unclosed_block_is_a_template_error() {
+        let tpl = "{{#if something}}hallo";
+        assert!(build_hb_registry(tpl, false).is_err());
+    }
+
     #[test]
     fn sprig_test_simple_add() {
         let tpl = "{{add p1.age p2.age}}";
@@ -129,6 +773,88 @@ mod tests {
         );
     }
 
+    #[test]
+    fn render_at_element_keeps_root_reachable() {
+        let tpl = "{{name}}.{{@root.domain}} {{@index}} {{../domain}}";
+        let hb = build_hb_registry(tpl, false).expect("couldn't build template");
+        let context = handlebars::Context::from(json!({
+            "domain": "example.com",
+            "tenants": [{"name": "acme"}, {"name": "globex"}],
+        }));
+
+        let path = ["tenants".to_owned(), "1".to_owned()];
+        assert_eq!(
+            render_at(&hb, TPLT, &context, &path, &[("index", json!(1))])
+                .expect("couldn't render template"),
+            "globex.example.com 1 example.com"
+        );
+    }
+
+    #[test]
+    fn file_names_are_not_html_escaped() {
+        let hb = build_hb_registry("out/{{name}}.txt", false).expect("couldn't build template");
+        let context = handlebars::Context::from(json!({"name": "tom&jerry a=b"}));
+        assert_eq!(
+            render_at(&unescaped(&hb), TPLT, &context, &[], &[]).expect("couldn't render template"),
+            "out/tom&jerry a=b.txt"
+        );
+    }
+
+    fn args(argv: &[&str]) -> Result<AppArgs, clap::Error> {
+        AppArgs::try_parse_from(std::iter::once("templatier").chain(argv.iter().copied()))?
+            .normalize()
+    }
+
+    #[test]
+    fn vars_default_to_an_empty_object() {
+        let args = args(&["page.hbs"]).expect("valid arguments");
+        assert_eq!(args.vars, None);
+        assert_eq!(load_vars(&args).expect("no vars to read"), json!({}));
+    }
+
+    #[test]
+    fn stdin_is_read_only_once() {
+        let Err(err) = args(&["-", "-"]) else {
+            panic!("stdin given twice should be rejected");
+        };
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn mode_needs_a_file_to_apply_to() {
+        let Err(err) = args(&["page.hbs", "--mode", "600", "--entry", "template"]) else {
+            panic!("--mode without a file should be rejected");
+        };
+        assert_eq!(err.kind(), ErrorKind::MissingRequiredArgument);
+        assert!(args(&["page.hbs", "--mode", "600", "--entry", "template=out.txt"]).is_ok());
+        assert!(args(&["page.hbs", "--mode", "600", "-o", "out.txt"]).is_ok());
+    }
+
+    #[test]
+    fn expr_takes_the_positional_as_vars_file() {
+        let parsed = args(&["-e", "{{name}}", "vars.json"]).expect("valid arguments");
+        assert_eq!(parsed.template, None);
+        assert_eq!(parsed.vars.as_deref(), Some("vars.json"));
+
+        let Err(err) = args(&["-e", "{{name}}", "page.hbs", "vars.json"]) else {
+            panic!("a template file together with --expr should be rejected");
+        };
+        assert_eq!(err.kind(), ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn env_helper_with_default() {
+        let path = std::env::var("PATH").expect("PATH is set");
+        let tpl = r#"{{env "PATH"}}/{{env "TEMPLATIER_TEST_UNSET" default="fallback"}}"#;
+        let hb = build_hb_registry(tpl, false).expect("couldn't build template");
+
+        assert_eq!(
+            hb.render(TPLT, &json!({}))
+                .expect("couldn't render template"),
+            format!("{path}/fallback")
+        );
+    }
+
     // #[test]
     // fn sprig_test_undefined_p2_age() {
     //     let tpl = "{{add p1.age p2.age includeZeros=true}}";
@@ -0,0 +1,95 @@
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+use crate::vars::{self, VarPath};
+
+/// Axes of a `--matrix`: an object whose keys are the axis names and whose values are arrays
+/// with the values each axis takes.
+#[derive(Debug)]
+pub struct Axes {
+    axes: Vec<(String, Vec<Value>)>,
+}
+
+impl Axes {
+    /// Reads the axes declared at `path` inside `vars`.
+    pub fn from_vars(vars: &Value, path: &VarPath) -> Result<Self, AppError> {
+        let shape_error = |message: String| AppError::VarsShape {
+            option: "--matrix".to_owned(),
+            message,
+        };
+        let axes = match vars::lookup(vars, &path.0) {
+            Some(Value::Object(axes)) => axes,
+            Some(value) => {
+                return Err(shape_error(format!(
+                    "`{path}` is {}, not an object of axes",
+                    vars::type_name(value)
+                )))
+            }
+            None => return Err(shape_error(format!("`{path}` isn't defined in the vars"))),
+        };
+        let axes = axes
+            .iter()
+            .map(|(name, values)| match values {
+                Value::Array(values) => Ok((name.clone(), values.clone())),
+                value => Err(shape_error(format!(
+                    "axis `{name}` of `{path}` is {}, not an array",
+                    vars::type_name(value)
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { axes })
+    }
+
+    /// Number of combinations.
+    pub fn count(&self) -> usize {
+        self.axes.iter().map(|(_, values)| values.len()).product()
+    }
+
+    /// The `index`th combination, as an object with one value per axis. The last axis changes
+    /// fastest, like in nested loops.
+    pub fn combination(&self, index: usize) -> Value {
+        let mut rest = index;
+        let mut combination = Map::new();
+        for (name, values) in self.axes.iter().rev() {
+            combination.insert(name.clone(), values[rest % values.len()].clone());
+            rest /= values.len();
+        }
+        Value::Object(combination)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Axes;
+    use crate::error::AppError;
+
+    #[test]
+    fn every_combination_once() {
+        let vars = json!({"matrix": {"env": ["dev", "prod"], "region": ["eu", "us", "ap"]}});
+        let axes = Axes::from_vars(&vars, &"matrix".parse().unwrap()).expect("valid axes");
+        assert_eq!(axes.count(), 6);
+        assert_eq!(axes.combination(0), json!({"env": "dev", "region": "eu"}));
+        assert_eq!(axes.combination(1), json!({"env": "dev", "region": "us"}));
+        assert_eq!(axes.combination(5), json!({"env": "prod", "region": "ap"}));
+    }
+
+    #[test]
+    fn empty_axes() {
+        let vars = json!({"m": {"env": ["dev"], "region": []}});
+        let axes = Axes::from_vars(&vars, &"m".parse().unwrap()).expect("valid axes");
+        assert_eq!(axes.count(), 0);
+    }
+
+    #[test]
+    fn axes_must_be_arrays() {
+        let vars = json!({"matrix": {"env": "dev"}});
+        match Axes::from_vars(&vars, &"matrix".parse().unwrap()) {
+            Err(AppError::VarsShape { message, .. }) => {
+                assert_eq!(message, "axis `env` of `matrix` is a string, not an array")
+            }
+            other => panic!("expected a shape error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+
+use serde_json::Value;
+
+use crate::error::{AppError, VarsError};
+use crate::input;
+
+/// Reads a stream of JSON values, one per line, keeping only the current line in memory.
+///
+/// Blank lines are skipped.
+pub struct Records<R> {
+    reader: R,
+    path: String,
+    line: usize,
+    buf: String,
+}
+
+/// Opens the stream at `path` (`-` is stdin).
+pub fn open(path: &str) -> Result<Records<Box<dyn BufRead>>, AppError> {
+    let reader: Box<dyn BufRead> = if path == input::STDIN {
+        Box::new(io::stdin().lock())
+    } else {
+        let file = File::open(path).map_err(|e| AppError::io(path, e))?;
+        Box::new(BufReader::new(file))
+    };
+    Ok(Records::new(reader, input::display_name(path)))
+}
+
+impl<R: BufRead> Records<R> {
+    pub fn new(reader: R, path: &str) -> Self {
+        Self {
+            reader,
+            path: path.to_owned(),
+            line: 0,
+            buf: String::new(),
+        }
+    }
+
+    fn parse_line(&self) -> Result<Value, AppError> {
+        serde_json::from_str(&self.buf).map_err(|e| {
+            // serde_json's message ends with the position inside the line, which is misleading
+            let message = e.to_string();
+            let message = message
+                .rsplit_once(" at line ")
+                .map_or(message.as_str(), |(message, _)| message);
+            AppError::Record {
+                path: self.path.clone(),
+                line: self.line,
+                contents: self.buf.trim_end().to_owned(),
+                source: VarsError::new(message).at(self.line, e.column()),
+            }
+        })
+    }
+}
+
+impl<R: BufRead> Iterator for Records<R> {
+    type Item = Result<Value, AppError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            self.line += 1;
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) if self.buf.trim().is_empty() => continue,
+                Ok(_) => return Some(self.parse_line()),
+                Err(e) => return Some(Err(AppError::io(self.path.clone(), e))),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::Records;
+    use crate::error::AppError;
+
+    #[test]
+    fn one_record_per_line() {
+        let stream = "{\"id\": 1}\n\n{\"id\": 2}\r\n[3]";
+        let records: Vec<_> = Records::new(stream.as_bytes(), "events.ndjson")
+            .collect::<Result<_, _>>()
+            .expect("valid stream");
+        assert_eq!(
+            records,
+            vec![json!({"id": 1}), json!({"id": 2}), json!([3])]
+        );
+    }
+
+    #[test]
+    fn errors_point_at_the_record_line() {
+        let stream = "{\"id\": 1}\n{\"id\": }\n{\"id\": 3}\n";
+        let mut records = Records::new(stream.as_bytes(), "events.ndjson");
+        assert!(records.next().expect("a record").is_ok());
+        match records.next().expect("a record") {
+            Err(AppError::Record {
+                line,
+                contents,
+                source,
+                ..
+            }) => {
+                assert_eq!(line, 2);
+                assert_eq!(contents, "{\"id\": }");
+                assert_eq!((source.line, source.column), (Some(2), Some(8)));
+                assert_eq!(source.message, "expected value");
+            }
+            other => panic!("expected a record error, got {other:?}"),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// What [`write_atomic`] ended up doing.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Written,
+    /// The file already had the same contents, so it wasn't touched.
+    Unchanged,
+}
+
+/// Parses a unix file mode written in octal (`644`, `0755`, ...).
+pub fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s, 8)
+        .ok()
+        .filter(|mode| *mode <= 0o7777)
+        .ok_or_else(|| format!("\"{s}\" isn't a valid octal file mode"))
+}
+
+/// Parses a delimiter given on the command line, turning the `\n`, `\r`, `\t`, `\0` and `\\`
+/// escapes into the characters they stand for.
+pub fn parse_delimiter(s: &str) -> Result<String, String> {
+    let mut delimiter = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            delimiter.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => delimiter.push('\n'),
+            Some('r') => delimiter.push('\r'),
+            Some('t') => delimiter.push('\t'),
+            Some('0') => delimiter.push('\0'),
+            Some('\\') => delimiter.push('\\'),
+            Some(other) => return Err(format!("unknown escape `\\{other}`")),
+            None => return Err("trailing `\\`".to_owned()),
+        }
+    }
+    Ok(delimiter)
+}
+
+/// Writes `contents` to `path` through a temporary file in the same directory that is then
+/// renamed over `path`, so readers never see a half written file.
+///
+/// Missing parent directories are created. If `path` already holds `contents` the file is left
+/// alone (keeping its mtime). `mode` sets the file permissions; without it the permissions of
+/// the file being replaced are kept.
+pub fn write_atomic(path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<WriteOutcome> {
+    if let Ok(current) = fs::read(path) {
+        if current == contents {
+            if let Some(mode) = mode {
+                set_mode(path, mode)?;
+            }
+            return Ok(WriteOutcome::Unchanged);
+        }
+    }
+
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let tmp = temp_path(dir, path);
+    let result = write_temp(&tmp, path, contents, mode).and_then(|_| fs::rename(&tmp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result.map(|_| WriteOutcome::Written)
+}
+
+fn write_temp(tmp: &Path, path: &Path, contents: &[u8], mode: Option<u32>) -> io::Result<()> {
+    let existing = fs::metadata(path).ok();
+    let mut file = open_temp(tmp, mode.or_else(|| existing.as_ref().and_then(mode_of)))?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    drop(file);
+
+    // the mode given on creation goes through the umask, this sets it exactly
+    match (mode, existing) {
+        (Some(mode), _) => set_mode(tmp, mode),
+        (None, Some(existing)) => fs::set_permissions(tmp, existing.permissions()),
+        (None, None) => Ok(()),
+    }
+}
+
+/// Creates the temporary file, already with `mode` so its contents are never readable by more
+/// users than the final file's.
+fn open_temp(tmp: &Path, mode: Option<u32>) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options.open(tmp)
+}
+
+fn temp_path(dir: &Path, path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dir.join(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+/// Permissions of the file `metadata` describes, on platforms that have file modes.
+#[cfg(unix)]
+pub fn mode_of(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn mode_of(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    // file modes only mean something on unix
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::{parse_delimiter, parse_mode, write_atomic, WriteOutcome};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("templatier-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn writes_exact_bytes_and_creates_parents() {
+        let dir = scratch_dir("output-write");
+        let path = dir.join("nested/out.conf");
+
+        let outcome = write_atomic(&path, b"hello", None).expect("couldn't write");
+        assert_eq!(outcome, WriteOutcome::Written);
+        assert_eq!(fs::read(&path).expect("couldn't read"), b"hello");
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn unchanged_contents_are_not_rewritten() {
+        let dir = scratch_dir("output-unchanged");
+        let path = dir.join("out.conf");
+        write_atomic(&path, b"same", None).expect("couldn't write");
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).unwrap();
+
+        let outcome = write_atomic(&path, b"same", None).expect("couldn't write");
+        assert_eq!(outcome, WriteOutcome::Unchanged);
+        assert_eq!(
+            fs::metadata(&path).and_then(|m| m.modified()).unwrap(),
+            modified
+        );
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_files_are_created_with_the_final_mode() {
+        use std::os::unix::fs::PermissionsExt;
+
+        use super::open_temp;
+
+        let dir = scratch_dir("output-temp-mode");
+        fs::create_dir_all(&dir).unwrap();
+        let tmp = dir.join(".secret.tmp");
+        let file = open_temp(&tmp, Some(0o600)).expect("couldn't create");
+        let mode = file.metadata().unwrap().permissions().mode();
+        assert_eq!(mode & 0o077, 0, "created with {mode:o}");
+
+        let path = dir.join("secret");
+        write_atomic(&path, b"s3cr3t", Some(0o640)).expect("couldn't write");
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o7777, 0o640);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn modes_are_octal() {
+        assert_eq!(parse_mode("644"), Ok(0o644));
+        assert_eq!(parse_mode("0755"), Ok(0o755));
+        assert!(parse_mode("999").is_err());
+    }
+
+    #[test]
+    fn delimiters_understand_escapes() {
+        assert_eq!(parse_delimiter(r"\n---\n").as_deref(), Ok("\n---\n"));
+        assert_eq!(parse_delimiter(r"a\\tb\0").as_deref(), Ok("a\\tb\0"));
+        assert!(parse_delimiter(r"\x").is_err());
+    }
+}
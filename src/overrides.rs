@@ -0,0 +1,116 @@
+use serde_json::Value;
+
+use crate::formats::has_leading_zero;
+use crate::vars::VarPath;
+
+/// A `path=value` pair given through `--set`, `--set-string` or `--set-json`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    /// The flag it came from, used to report where a value came from.
+    pub flag: &'static str,
+    pub path: VarPath,
+    pub value: Value,
+}
+
+impl Assignment {
+    /// Name shown by `--explain-var` for this assignment.
+    pub fn source(&self) -> String {
+        format!("{} {}", self.flag, self.path)
+    }
+}
+
+/// Parses `--set` values: `true`, `false`, `null` and integers become their JSON type, anything
+/// else is a string. Like in Helm, floats are left as strings so `1.10` doesn't become `1.1`.
+pub fn parse_set(s: &str) -> Result<Assignment, String> {
+    let (path, raw) = split_assignment(s)?;
+    Ok(Assignment {
+        flag: "--set",
+        path,
+        value: typed_literal(raw),
+    })
+}
+
+/// Parses `--set-string` values, which are always strings.
+pub fn parse_set_string(s: &str) -> Result<Assignment, String> {
+    let (path, raw) = split_assignment(s)?;
+    Ok(Assignment {
+        flag: "--set-string",
+        path,
+        value: Value::String(raw.to_owned()),
+    })
+}
+
+/// Parses `--set-json` values, which are JSON documents.
+pub fn parse_set_json(s: &str) -> Result<Assignment, String> {
+    let (path, raw) = split_assignment(s)?;
+    let value = serde_json::from_str(raw).map_err(|e| format!("invalid JSON for {path}: {e}"))?;
+    Ok(Assignment {
+        flag: "--set-json",
+        path,
+        value,
+    })
+}
+
+/// Splits `path=value` at the first `=` that isn't escaped with `\`.
+fn split_assignment(s: &str) -> Result<(VarPath, &str), String> {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '=' if !escaped => return Ok((s[..i].parse()?, &s[i + 1..])),
+            _ => escaped = false,
+        }
+    }
+    Err(format!("\"{s}\" should look like path.to.key=value"))
+}
+
+fn typed_literal(raw: &str) -> Value {
+    match raw {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "null" => Value::Null,
+        _ => match raw.parse::<i64>() {
+            Ok(n) if !has_leading_zero(raw) => Value::from(n),
+            _ => Value::String(raw.to_owned()),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{parse_set, parse_set_json, parse_set_string};
+
+    #[test]
+    fn set_parses_typed_literals() {
+        let value = |s: &str| parse_set(s).expect("valid assignment").value;
+        assert_eq!(value("a=8080"), json!(8080));
+        assert_eq!(value("a=-3"), json!(-3));
+        assert_eq!(value("a=true"), json!(true));
+        assert_eq!(value("a=null"), json!(null));
+        assert_eq!(value("a=1.10"), json!("1.10"));
+        assert_eq!(value("a=007"), json!("007"));
+        assert_eq!(value("a=x=y"), json!("x=y"));
+        assert_eq!(value("a="), json!(""));
+    }
+
+    #[test]
+    fn set_string_and_set_json() {
+        let assignment = parse_set_string("image.tag=123").expect("valid assignment");
+        assert_eq!(assignment.value, json!("123"));
+        assert_eq!(assignment.source(), "--set-string image.tag");
+
+        let assignment = parse_set_json(r#"servers[0]={"port": 80}"#).expect("valid assignment");
+        assert_eq!(assignment.value, json!({"port": 80}));
+        assert!(parse_set_json("a={").is_err());
+    }
+
+    #[test]
+    fn keys_can_escape_equal_signs() {
+        let assignment = parse_set(r"labels.a\=b=c").expect("valid assignment");
+        assert_eq!(assignment.path.to_string(), "labels.a=b");
+        assert_eq!(assignment.value, json!("c"));
+        assert!(parse_set("just-a-key").is_err());
+    }
+}
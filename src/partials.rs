@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use handlebars::Handlebars;
+
+use crate::error::AppError;
+use crate::templates::{self, Sources};
+
+/// Registers every file under each of `dirs` as a partial named by its path relative to that
+/// directory, without the extension: `partials/layouts/base.hbs` is `{{> layouts/base}}`.
+///
+/// Hidden files and directories are skipped. Two files giving the same name, or a name already
+/// taken by a template, are an error. Every partial is added to `sources`.
+pub fn register(
+    handlebars: &mut Handlebars,
+    dirs: &[String],
+    sources: &mut Sources,
+) -> Result<(), AppError> {
+    let mut partials = BTreeMap::new();
+    for dir in dirs {
+        collect(Path::new(dir), "", &mut partials)?;
+    }
+
+    for (name, path) in &partials {
+        let path = path.display().to_string();
+        if handlebars.get_template(name).is_some() {
+            return Err(AppError::PartialConflict {
+                name: name.clone(),
+                path,
+                other: "the template of the same name".to_owned(),
+            });
+        }
+        let contents = fs::read_to_string(&path).map_err(|e| AppError::io(&path, e))?;
+        let template =
+            templates::compile(name, &contents).map_err(|source| AppError::Template {
+                path: path.clone(),
+                contents: contents.clone(),
+                source: Box::new(source),
+            })?;
+        handlebars.register_template(name, template);
+        sources.insert(name, path, contents);
+    }
+    Ok(())
+}
+
+/// Adds the files of the directory `relative` of `root` to `partials`, by partial name.
+fn collect(
+    root: &Path,
+    relative: &str,
+    partials: &mut BTreeMap<String, PathBuf>,
+) -> Result<(), AppError> {
+    let dir = match relative {
+        "" => root.to_path_buf(),
+        relative => root.join(relative),
+    };
+    let mut children = fs::read_dir(&dir)
+        .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| AppError::io(dir.display().to_string(), e))?;
+    children.sort_by_key(|child| child.file_name());
+
+    for child in children {
+        let path = child.path();
+        let file_name = child.file_name();
+        let file_name = file_name.to_string_lossy();
+        if file_name.starts_with('.') {
+            continue;
+        }
+        let relative = if relative.is_empty() {
+            file_name.into_owned()
+        } else {
+            format!("{relative}/{file_name}")
+        };
+        if path.is_dir() {
+            collect(root, &relative, partials)?;
+            continue;
+        }
+
+        let name = match relative.rsplit_once('.') {
+            Some((name, extension)) if !extension.contains('/') => name.to_owned(),
+            _ => relative,
+        };
+        if let Some(other) = partials.get(&name) {
+            return Err(AppError::PartialConflict {
+                name,
+                path: path.display().to_string(),
+                other: other.display().to_string(),
+            });
+        }
+        partials.insert(name, path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::register;
+    use crate::error::AppError;
+    use crate::templates::Sources;
+    use crate::{build_hb_registry, TPLT};
+
+    #[test]
+    fn partials_are_named_by_relative_path() {
+        let root = std::env::temp_dir().join(format!("templatier-partials-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("shared/layouts")).unwrap();
+        fs::write(root.join("shared/header.hbs"), "# {{title}}").unwrap();
+        fs::write(root.join("shared/layouts/license.txt"), "MIT").unwrap();
+        fs::write(root.join("shared/.hidden.hbs"), "{{#if}}").unwrap();
+        fs::create_dir_all(root.join("other")).unwrap();
+        fs::write(root.join("other/header.html"), "<h1>").unwrap();
+
+        let mut hb = build_hb_registry("{{> header}} {{> layouts/license}}", false).unwrap();
+        let shared = root.join("shared").display().to_string();
+        register(
+            &mut hb,
+            std::slice::from_ref(&shared),
+            &mut Sources::default(),
+        )
+        .expect("couldn't register partials");
+        assert_eq!(
+            hb.render(TPLT, &json!({"title": "Hi"})).unwrap(),
+            "# Hi MIT"
+        );
+
+        let other = root.join("other").display().to_string();
+        let mut hb = build_hb_registry("", false).unwrap();
+        match register(&mut hb, &[shared, other], &mut Sources::default()) {
+            Err(AppError::PartialConflict { name, .. }) => assert_eq!(name, "header"),
+            other => panic!("expected a conflict, got {other:?}"),
+        }
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
@@ -149,3 +149,11 @@ pub fn add_date_helpers(x: &mut Handlebars) {
     x.register_helper("date_format", Box::new(date_format));
     x.register_helper("now", Box::new(now));
 }
+
+pub fn add_os_helpers(x: &mut Handlebars) {
+    handlebars_helper!(env: |name: String, {default: str = ""}| {
+        std::env::var(name).unwrap_or_else(|_| default.to_owned())
+    });
+
+    x.register_helper("env", Box::new(env));
+}
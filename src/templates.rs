@@ -0,0 +1,319 @@
+use std::collections::BTreeMap;
+
+use handlebars::template::TemplateElement;
+use handlebars::{Handlebars, RenderError, Template, TemplateError};
+
+use crate::error::AppError;
+use crate::{input, layout};
+
+/// `--template name=path`: a template registered under `name`, which can be rendered with
+/// `--entry name` or included from the others as `{{> name}}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Named {
+    pub name: String,
+    pub path: String,
+}
+
+/// `--entry name[=path]`: a template to render and the file its output goes to, if it has its
+/// own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub output: Option<String>,
+}
+
+/// Parses `--template` values.
+pub fn parse_named(s: &str) -> Result<Named, String> {
+    match s.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => Ok(Named {
+            name: name.to_owned(),
+            path: path.to_owned(),
+        }),
+        _ => Err(format!(
+            "\"{s}\" should look like name=path/to/template.hbs"
+        )),
+    }
+}
+
+/// Parses `--entry` values.
+pub fn parse_entry(s: &str) -> Result<Entry, String> {
+    let (name, output) = match s.split_once('=') {
+        Some((name, output)) => (name, Some(output)),
+        None => (s, None),
+    };
+    if name.is_empty() || output == Some("") {
+        return Err(format!(
+            "\"{s}\" should look like name or name=path/to/output"
+        ));
+    }
+    Ok(Entry {
+        name: name.to_owned(),
+        output: output.map(str::to_owned),
+    })
+}
+
+/// Compiles `contents` as the template `name`, naming its blocks after it too. Handlebars leaves
+/// them unnamed, so errors raised in a partial block (`{{#> layout}}...{{/layout}}`) or an
+/// inline partial would be reported against the partial rendering them.
+pub fn compile(name: &str, contents: &str) -> Result<Template, TemplateError> {
+    let mut template = Template::compile_with_name(contents, name.to_owned())?;
+    name_blocks(&mut template, name);
+    Ok(template)
+}
+
+fn name_blocks(template: &mut Template, name: &str) {
+    for element in &mut template.elements {
+        let blocks = match element {
+            TemplateElement::HelperBlock(helper) => [&mut helper.template, &mut helper.inverse],
+            TemplateElement::DecoratorBlock(decorator)
+            | TemplateElement::PartialBlock(decorator) => [&mut decorator.template, &mut None],
+            _ => continue,
+        };
+        for block in blocks.into_iter().flatten() {
+            block.name.get_or_insert_with(|| name.to_owned());
+            name_blocks(block, name);
+        }
+    }
+}
+
+/// A template of the run, as it was read.
+#[derive(Debug)]
+pub struct Source {
+    pub name: String,
+    /// Where it came from, for diagnostics.
+    pub path: String,
+    pub contents: String,
+}
+
+impl Source {
+    fn error(&self, source: TemplateError) -> AppError {
+        AppError::Template {
+            path: self.path.clone(),
+            contents: self.contents.clone(),
+            source: Box::new(source),
+        }
+    }
+}
+
+/// Path and contents of every template and partial registered, by name, so render errors can be
+/// reported against the one they were raised in.
+#[derive(Debug, Default)]
+pub struct Sources {
+    by_name: BTreeMap<String, (String, String)>,
+}
+
+impl Sources {
+    pub fn insert(&mut self, name: &str, path: impl Into<String>, contents: impl Into<String>) {
+        self.by_name
+            .insert(name.to_owned(), (path.into(), contents.into()));
+    }
+
+    /// Turns an error raised while rendering the template read from `path` into an
+    /// [`AppError`] pointing at the template or partial that failed. When that one isn't known,
+    /// the error is reported against `path`, without a position.
+    pub fn render_error(&self, path: &str, mut source: RenderError) -> AppError {
+        let failed = source
+            .template_name
+            .as_ref()
+            .and_then(|name| self.by_name.get(name));
+        match failed {
+            Some((path, contents)) => AppError::Render {
+                path: path.clone(),
+                contents: Some(contents.clone()),
+                source,
+            },
+            None => {
+                source.line_no = None;
+                source.column_no = None;
+                AppError::Render {
+                    path: path.to_owned(),
+                    contents: None,
+                    source,
+                }
+            }
+        }
+    }
+}
+
+/// Every template of a run: the positional one (or `--expr`) and the `--template` ones. They're
+/// all registered in the same handlebars registry, so any of them can be rendered or included
+/// from the others.
+#[derive(Debug)]
+pub struct Templates {
+    sources: Vec<Source>,
+}
+
+impl Templates {
+    /// Reads the `named` templates, after `main` if there's one.
+    pub fn read(main: Option<Source>, named: &[Named]) -> Result<Self, AppError> {
+        let mut sources: Vec<_> = main.into_iter().collect();
+        for template in named {
+            sources.push(Source {
+                name: template.name.clone(),
+                path: input::display_name(&template.path).to_owned(),
+                contents: input::read_to_string(&template.path)?,
+            });
+        }
+        Ok(Self { sources })
+    }
+
+    /// The template registered as `name`.
+    pub fn get(&self, name: &str) -> &Source {
+        self.sources
+            .iter()
+            .find(|source| source.name == name)
+            .expect("entries are checked against the template names")
+    }
+
+    /// Registers every template as written, so parse errors point at their source, and adds
+    /// them to `sources`.
+    pub fn register(
+        &self,
+        handlebars: &mut Handlebars,
+        sources: &mut Sources,
+    ) -> Result<(), AppError> {
+        for source in &self.sources {
+            let template = compile(&source.name, &source.contents).map_err(|e| source.error(e))?;
+            handlebars.register_template(&source.name, template);
+            sources.insert(&source.name, &source.path, &source.contents);
+        }
+        Ok(())
+    }
+
+    /// Wraps in their layout the templates that declare one with `{{!< name}}`, and the
+    /// `entries` in `default` when they don't. Layouts are partials, so this goes after
+    /// registering them.
+    pub fn apply_layouts(
+        &self,
+        handlebars: &mut Handlebars,
+        default: Option<&str>,
+        entries: &[Entry],
+    ) -> Result<(), AppError> {
+        for source in &self.sources {
+            let is_entry = entries.iter().any(|entry| entry.name == source.name);
+            let Some(name) = layout::declared(&source.contents).or(default.filter(|_| is_entry))
+            else {
+                continue;
+            };
+            let name = layout::resolve(handlebars, name, &source.path)?;
+            let template = handlebars
+                .get_template(&source.name)
+                .expect("templates are registered before their layouts");
+            let wrapped = layout::wrap(template, &name);
+            handlebars.register_template(&source.name, wrapped);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{compile, parse_entry, parse_named, Entry, Source, Sources, Templates};
+    use crate::error::AppError;
+    use crate::new_hb_registry;
+
+    #[test]
+    fn entries_render_and_include_each_other() {
+        let source = |name: &str, contents: &str| Source {
+            name: name.to_owned(),
+            path: format!("{name}.hbs"),
+            contents: contents.to_owned(),
+        };
+        let templates = Templates {
+            sources: vec![
+                source("page", "{{!< base}}{{> footer}}"),
+                source("footer", "(c) {{year}}"),
+                source("base", "<main>{{> @partial-block}}</main>"),
+            ],
+        };
+        let mut hb = new_hb_registry(false);
+        templates
+            .register(&mut hb, &mut Sources::default())
+            .expect("valid templates");
+        let entries = [parse_entry("page=out/index.html").unwrap()];
+        templates
+            .apply_layouts(&mut hb, None, &entries)
+            .expect("the layout is registered");
+
+        let vars = json!({"year": 2024});
+        assert_eq!(hb.render("page", &vars).unwrap(), "<main>(c) 2024</main>");
+        assert_eq!(hb.render("footer", &vars).unwrap(), "(c) 2024");
+    }
+
+    #[test]
+    fn render_errors_point_at_the_failing_partial() {
+        let mut hb = new_hb_registry(false);
+        hb.register_template_string("page", "line 1\n{{> bad}}")
+            .unwrap();
+        hb.register_partial("bad", "ok\n  {{add \"x\" 1}}").unwrap();
+        let mut sources = Sources::default();
+        sources.insert("page", "page.hbs", "line 1\n{{> bad}}");
+
+        let error = || {
+            hb.render("page", &json!({}))
+                .expect_err("render should fail")
+        };
+        match sources.render_error("page.hbs", error()) {
+            AppError::Render {
+                path,
+                contents,
+                source,
+            } => {
+                assert_eq!(path, "page.hbs");
+                assert_eq!(contents, None);
+                assert_eq!((source.line_no, source.column_no), (None, None));
+            }
+            other => panic!("expected a render error, got {other:?}"),
+        }
+
+        sources.insert("bad", "partials/bad.hbs", "ok\n  {{add \"x\" 1}}");
+        match sources.render_error("page.hbs", error()) {
+            AppError::Render { path, source, .. } => {
+                assert_eq!(path, "partials/bad.hbs");
+                assert_eq!((source.line_no, source.column_no), (Some(2), Some(3)));
+            }
+            other => panic!("expected a render error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn blocks_are_named_after_their_template() {
+        let mut hb = new_hb_registry(false);
+        hb.register_partial("box", "[\n{{> @partial-block}}]")
+            .unwrap();
+        let page = "{{#> box}}\n{{#if true}}{{add \"x\" 1}}{{/if}}{{/box}}";
+        hb.register_template("page", compile("page", page).expect("valid template"));
+
+        let err = hb
+            .render("page", &json!({}))
+            .expect_err("render should fail");
+        assert_eq!(err.template_name.as_deref(), Some("page"));
+        assert_eq!((err.line_no, err.column_no), (Some(2), Some(13)));
+    }
+
+    #[test]
+    fn parse_flags() {
+        let named = parse_named("mail=tpl/a=b.hbs").unwrap();
+        assert_eq!(
+            (named.name.as_str(), named.path.as_str()),
+            ("mail", "tpl/a=b.hbs")
+        );
+        assert!(parse_named("mail").is_err());
+        assert!(parse_named("=mail.hbs").is_err());
+
+        assert_eq!(
+            parse_entry("mail").unwrap(),
+            Entry {
+                name: "mail".to_owned(),
+                output: None
+            }
+        );
+        assert_eq!(
+            parse_entry("mail=out/mail.txt").unwrap().output.as_deref(),
+            Some("out/mail.txt")
+        );
+        assert!(parse_entry("mail=").is_err());
+    }
+}
@@ -0,0 +1,573 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use handlebars::{
+    Context, Handlebars, Helper, HelperResult, JsonTruthy, Output, RenderContext, RenderError,
+    RenderErrorReason,
+};
+use serde_json::Value;
+use thiserror::Error;
+
+use crate::error::AppError;
+use crate::templates::{self, Sources};
+use crate::{layout, output};
+
+/// File at the root of a template directory listing more patterns to ignore.
+pub const IGNORE_FILE: &str = ".templatierignore";
+
+/// Suffix of the files that are rendered; everything else is copied as is.
+const TEMPLATE_SUFFIX: &str = ".hbs";
+
+/// Patterns of files left out of a directory render, in a subset of the `.gitignore` syntax.
+///
+/// `*` and `?` match within a path component and `**` across components. Patterns without a `/`
+/// match the name of a file or directory at any depth, the rest match the whole path relative to
+/// the template directory. A trailing `/` only matches directories. There's no `!` negation,
+/// `[...]` classes or `\` escapes: those characters match themselves.
+#[derive(Debug, Default)]
+pub struct Ignore {
+    patterns: Vec<Pattern>,
+}
+
+#[derive(Debug)]
+struct Pattern {
+    glob: Vec<char>,
+    anchored: bool,
+    dir_only: bool,
+}
+
+impl Ignore {
+    /// Patterns of the `.templatierignore` file at the root of `dir` (if there's one) plus
+    /// `extra`.
+    pub fn load(dir: &Path, extra: &[String]) -> Result<Self, AppError> {
+        let path = dir.join(IGNORE_FILE);
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(AppError::io(path.display().to_string(), e)),
+        };
+        Ok(Self::new(
+            contents.lines().chain(extra.iter().map(String::as_str)),
+        ))
+    }
+
+    /// Parses one pattern per item, skipping blank ones and `#` comments.
+    pub fn new<'a>(patterns: impl IntoIterator<Item = &'a str>) -> Self {
+        let patterns = patterns
+            .into_iter()
+            .map(str::trim)
+            .filter(|pattern| !pattern.is_empty() && !pattern.starts_with('#'))
+            .map(|pattern| {
+                let (pattern, dir_only) = match pattern.strip_suffix('/') {
+                    Some(pattern) => (pattern, true),
+                    None => (pattern, false),
+                };
+                Pattern {
+                    glob: pattern.trim_start_matches('/').chars().collect(),
+                    anchored: pattern.contains('/'),
+                    dir_only,
+                }
+            })
+            .collect();
+        Self { patterns }
+    }
+
+    /// Whether `path` (relative to the template directory, `/` separated) is ignored.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        self.patterns.iter().any(|pattern| {
+            let target = if pattern.anchored { path } else { name };
+            (is_dir || !pattern.dir_only)
+                && glob_match(&pattern.glob, &target.chars().collect::<Vec<_>>())
+        })
+    }
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        ['*', '*', rest @ ..] => {
+            // `**/` also matches no directory at all
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+                || rest.first() == Some(&'/') && glob_match(&rest[1..], text)
+        }
+        ['*', rest @ ..] => (0..=text.len())
+            .take_while(|i| *i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(rest, &text[i..])),
+        ['?', rest @ ..] => text.first().is_some_and(|c| *c != '/') && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+/// What to do with each entry of the template directory.
+enum Action {
+    CreateDir,
+    Render { name: String, contents: String },
+    Copy,
+}
+
+/// How a template directory is rendered.
+#[derive(Debug, Default)]
+pub struct Options {
+    /// Files and directories left out.
+    pub ignore: Ignore,
+    /// Layout of the templates that don't declare one.
+    pub layout: Option<String>,
+    /// Permissions of the output files. By default they keep the ones of their source.
+    pub mode: Option<u32>,
+}
+
+struct Entry {
+    source: PathBuf,
+    target: PathBuf,
+    action: Action,
+}
+
+/// Renders the template directory `source` into `target`.
+///
+/// File and directory names are rendered too, files ending in `.hbs` are rendered (without the
+/// suffix, and inside the layout they declare with `{{!< name}}` or else `options.layout`) and
+/// everything else is copied verbatim. Entries whose name renders empty are left out, and so are
+/// templates (or names) that call `{{skip_file}}`.
+///
+/// Every template is registered in `handlebars` before rendering, under its path relative to
+/// `source`, so nothing is written if one of them doesn't parse. `sources` has the partials
+/// already registered, to report render errors raised in them.
+pub fn render(
+    source: &Path,
+    target: &Path,
+    handlebars: &mut Handlebars<'static>,
+    mut sources: Sources,
+    vars: Value,
+    options: &Options,
+) -> Result<(), AppError> {
+    let Options {
+        ignore,
+        layout,
+        mode,
+    } = options;
+    handlebars.register_helper("skip_file", Box::new(skip_file));
+    let names = crate::unescaped(handlebars);
+    let context = Context::from(vars);
+    let mut entries = Vec::new();
+    let walk = Walk {
+        handlebars: &names,
+        context: &context,
+        ignore,
+        sources: &sources,
+        root: source,
+    };
+    walk.plan("", target, &mut entries)?;
+
+    for entry in &entries {
+        if let Action::Render { name, contents } = &entry.action {
+            let path = entry.source.display().to_string();
+            let template_error = |source| AppError::Template {
+                path: path.clone(),
+                contents: contents.clone(),
+                source: Box::new(source),
+            };
+            let template = templates::compile(name, contents).map_err(template_error)?;
+            handlebars.register_template(name, template);
+            sources.insert(name, &path, contents);
+            if let Some(layout) = layout::declared(contents).or(layout.as_deref()) {
+                let layout = layout::resolve(handlebars, layout, &path)?;
+                let template = handlebars
+                    .get_template(name)
+                    .expect("the template was just registered");
+                let wrapped = layout::wrap(template, &layout);
+                handlebars.register_template(name, wrapped);
+            }
+        }
+    }
+
+    fs::create_dir_all(target).map_err(|e| AppError::io(target.display().to_string(), e))?;
+    for entry in entries {
+        let target_name = entry.target.display().to_string();
+        let contents = match entry.action {
+            Action::CreateDir => {
+                fs::create_dir_all(&entry.target).map_err(|e| AppError::io(target_name, e))?;
+                continue;
+            }
+            Action::Render { name, .. } => match handlebars.render_with_context(&name, &context) {
+                Ok(rendered) => rendered.into_bytes(),
+                Err(e) if is_skip(&e) => continue,
+                Err(e) => return Err(sources.render_error(&entry.source.display().to_string(), e)),
+            },
+            Action::Copy => fs::read(&entry.source)
+                .map_err(|e| AppError::io(entry.source.display().to_string(), e))?,
+        };
+        let mode = match mode {
+            Some(mode) => Some(*mode),
+            None => output::mode_of(
+                &fs::metadata(&entry.source)
+                    .map_err(|e| AppError::io(entry.source.display().to_string(), e))?,
+            ),
+        };
+        output::write_atomic(&entry.target, &contents, mode)
+            .map_err(|e| AppError::io(target_name, e))?;
+    }
+    Ok(())
+}
+
+/// The template directory being walked, and what's needed to render its names.
+struct Walk<'a> {
+    /// Doesn't HTML-escape, names aren't HTML.
+    handlebars: &'a Handlebars<'a>,
+    context: &'a Context,
+    ignore: &'a Ignore,
+    sources: &'a Sources,
+    root: &'a Path,
+}
+
+impl Walk<'_> {
+    /// Walks the directory `relative` of `root` (in name order), rendering the names of its
+    /// entries and reading the templates.
+    fn plan(
+        &self,
+        relative: &str,
+        target: &Path,
+        entries: &mut Vec<Entry>,
+    ) -> Result<(), AppError> {
+        let dir = match relative {
+            "" => self.root.to_path_buf(),
+            relative => self.root.join(relative),
+        };
+        let io_error = |path: &Path, e| AppError::io(path.display().to_string(), e);
+        let mut children = fs::read_dir(&dir)
+            .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+            .map_err(|e| io_error(&dir, e))?;
+        children.sort_by_key(|child| child.file_name());
+
+        for child in children {
+            let source = child.path();
+            let name = child.file_name().into_string().map_err(|_| {
+                io_error(
+                    &source,
+                    io::Error::new(io::ErrorKind::InvalidData, "file name isn't valid UTF-8"),
+                )
+            })?;
+            let path = if relative.is_empty() {
+                name.clone()
+            } else {
+                format!("{relative}/{name}")
+            };
+            // follows symlinks
+            let is_dir = fs::metadata(&source)
+                .map_err(|e| io_error(&source, e))?
+                .is_dir();
+            if path == IGNORE_FILE || self.ignore.is_ignored(&path, is_dir) {
+                continue;
+            }
+
+            let is_template = !is_dir && name.ends_with(TEMPLATE_SUFFIX);
+            let Some(mut target_name) = self.render_name(&source, &name)? else {
+                continue;
+            };
+            if is_template {
+                if let Some(stripped) = target_name.strip_suffix(TEMPLATE_SUFFIX) {
+                    target_name.truncate(stripped.len());
+                }
+            }
+            // `{{optional_file_name}}`
+            if target_name.trim().is_empty() {
+                continue;
+            }
+            if target_name == "." || target_name == ".." || target_name.contains(['/', '\\']) {
+                return Err(AppError::OutputPath {
+                    path: source.display().to_string(),
+                    message: format!(
+                        "`{name}` gives `{target_name}`, which isn't a valid file name"
+                    ),
+                });
+            }
+            let target = target.join(&target_name);
+
+            if is_dir {
+                entries.push(Entry {
+                    source: source.clone(),
+                    target: target.clone(),
+                    action: Action::CreateDir,
+                });
+                self.plan(&path, &target, entries)?;
+            } else if is_template {
+                let contents = fs::read_to_string(&source).map_err(|e| io_error(&source, e))?;
+                entries.push(Entry {
+                    source,
+                    target,
+                    action: Action::Render {
+                        name: path,
+                        contents,
+                    },
+                });
+            } else {
+                entries.push(Entry {
+                    source,
+                    target,
+                    action: Action::Copy,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders a file or directory name, which can use the vars too (`{{service}}.yaml.hbs`).
+    /// Returns `None` when the name calls `skip_file`.
+    fn render_name(&self, source: &Path, name: &str) -> Result<Option<String>, AppError> {
+        if !name.contains("{{") {
+            return Ok(Some(name.to_owned()));
+        }
+        match self
+            .handlebars
+            .render_template_with_context(name, self.context)
+        {
+            Ok(rendered) => Ok(Some(rendered)),
+            Err(e) if is_skip(&e) => Ok(None),
+            // raised in the name itself rather than in a partial it includes
+            Err(e) if e.template_name.is_none() => Err(AppError::Render {
+                path: source.display().to_string(),
+                contents: Some(name.to_owned()),
+                source: e,
+            }),
+            Err(e) => Err(self.sources.render_error(&source.display().to_string(), e)),
+        }
+    }
+}
+
+/// Raised by the `skip_file` helper to leave the file being rendered out of the output.
+#[derive(Debug, Error)]
+#[error("file skipped with skip_file")]
+struct SkipFile;
+
+/// `{{skip_file}}`: stops rendering the current file and leaves it out of the output.
+///
+/// `{{skip_file if=cond}}` and `{{skip_file unless=cond}}` only skip depending on `cond`, which
+/// is handy in file names, where `{{/if}}` can't be written.
+fn skip_file(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    _: &mut dyn Output,
+) -> HelperResult {
+    let truthy = |name| {
+        h.hash_get(name)
+            .is_some_and(|value| value.value().is_truthy(false))
+    };
+    let skip = match (h.hash_get("if"), h.hash_get("unless")) {
+        (None, None) => true,
+        _ => truthy("if") || (h.hash_get("unless").is_some() && !truthy("unless")),
+    };
+    if skip {
+        Err(RenderErrorReason::NestedError(Box::new(SkipFile)).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn is_skip(err: &RenderError) -> bool {
+    matches!(err.reason(), RenderErrorReason::NestedError(e) if e.is::<SkipFile>())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::json;
+
+    use super::{render, Ignore, Options};
+    use crate::new_hb_registry;
+    use crate::templates::Sources;
+
+    #[test]
+    fn ignore_patterns() {
+        let ignore = Ignore::new([
+            "# comment",
+            "*.swp",
+            "build/",
+            "/docs/**/*.md",
+            "?.tmp",
+            "[ab].txt",
+        ]);
+        assert!(ignore.is_ignored("a/b/.config.swp", false));
+        assert!(ignore.is_ignored("x/build", true));
+        assert!(!ignore.is_ignored("x/build", false));
+        assert!(ignore.is_ignored("docs/intro.md", false));
+        assert!(ignore.is_ignored("docs/a/b/intro.md", false));
+        assert!(!ignore.is_ignored("src/docs/intro.md", false));
+        assert!(ignore.is_ignored("a.tmp", false));
+        assert!(!ignore.is_ignored("ab.tmp", false));
+        assert!(ignore.is_ignored("[ab].txt", false));
+        assert!(!ignore.is_ignored("a.txt", false));
+    }
+
+    #[test]
+    fn renders_names_and_templates_and_copies_the_rest() {
+        let root = std::env::temp_dir().join(format!("templatier-tree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let source = root.join("template");
+        fs::create_dir_all(source.join("{{service}}")).unwrap();
+        fs::write(
+            source.join("{{service}}/config.yaml.hbs"),
+            "port: {{port}}\n",
+        )
+        .unwrap();
+        fs::write(source.join("logo.txt"), "{{not rendered}}").unwrap();
+        fs::write(source.join("notes.swp"), "").unwrap();
+        fs::write(
+            source.join("{{skip_file unless=docker}}Dockerfile"),
+            "FROM x",
+        )
+        .unwrap();
+        fs::create_dir_all(source.join("{{skip_file if=no_docs}}docs")).unwrap();
+        fs::write(source.join("{{optional}}"), "").unwrap();
+        fs::write(
+            source.join("ci.yml.hbs"),
+            "{{#unless ci}}{{skip_file}}{{/unless}}ci",
+        )
+        .unwrap();
+        fs::write(source.join(super::IGNORE_FILE), "*.swp\n").unwrap();
+
+        let target = root.join("out");
+        let mut handlebars = new_hb_registry(false);
+        let vars = json!({"service": "api", "port": 8080, "no_docs": true});
+        render(
+            &source,
+            &target,
+            &mut handlebars,
+            Sources::default(),
+            vars,
+            &Options {
+                ignore: Ignore::load(&source, &[]).unwrap(),
+                ..Options::default()
+            },
+        )
+        .expect("couldn't render tree");
+
+        assert_eq!(
+            fs::read_to_string(target.join("api/config.yaml")).unwrap(),
+            "port: 8080\n"
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("logo.txt")).unwrap(),
+            "{{not rendered}}"
+        );
+        assert!(!target.join("notes.swp").exists());
+        assert!(!target.join("Dockerfile").exists());
+        assert!(!target.join("ci.yml").exists());
+        assert_eq!(fs::read_dir(&target).unwrap().count(), 2);
+        assert!(!target.join(super::IGNORE_FILE).exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn skip_file_keeps_entries_whose_condition_does_not_hold() {
+        let root =
+            std::env::temp_dir().join(format!("templatier-tree-skip-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let source = root.join("template");
+        fs::create_dir_all(source.join("{{skip_file if=no_docs}}docs")).unwrap();
+        fs::write(
+            source.join("{{skip_file if=no_docs}}docs/index.md"),
+            "# docs",
+        )
+        .unwrap();
+        fs::write(
+            source.join("{{skip_file unless=docker}}Dockerfile"),
+            "FROM x",
+        )
+        .unwrap();
+
+        let target = root.join("out");
+        render(
+            &source,
+            &target,
+            &mut new_hb_registry(false),
+            Sources::default(),
+            json!({"docker": true, "no_docs": false}),
+            &Options::default(),
+        )
+        .expect("couldn't render tree");
+
+        assert_eq!(
+            fs::read_to_string(target.join("Dockerfile")).unwrap(),
+            "FROM x"
+        );
+        assert_eq!(
+            fs::read_to_string(target.join("docs/index.md")).unwrap(),
+            "# docs"
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn names_are_not_html_escaped() {
+        let root =
+            std::env::temp_dir().join(format!("templatier-tree-names-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let source = root.join("template");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("{{svc}}.txt.hbs"), "{{svc}}").unwrap();
+
+        let target = root.join("out");
+        render(
+            &source,
+            &target,
+            &mut new_hb_registry(false),
+            Sources::default(),
+            json!({"svc": "tom&jerry a=b"}),
+            &Options::default(),
+        )
+        .expect("couldn't render tree");
+
+        assert_eq!(
+            fs::read_to_string(target.join("tom&jerry a=b.txt")).unwrap(),
+            "tom&amp;jerry a&#x3D;b"
+        );
+
+        let _ = fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn default_layout_wraps_templates_without_their_own() {
+        let root =
+            std::env::temp_dir().join(format!("templatier-tree-layout-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let source = root.join("template");
+        fs::create_dir_all(&source).unwrap();
+        fs::write(source.join("page.txt.hbs"), "hi {{name}}").unwrap();
+        fs::write(source.join("own.txt.hbs"), "{{!< plain}}own").unwrap();
+
+        let target = root.join("out");
+        let mut handlebars = new_hb_registry(false);
+        handlebars
+            .register_partial("layouts/base", "[{{> @partial-block}}]")
+            .unwrap();
+        handlebars
+            .register_partial("plain", "({{> @partial-block}})")
+            .unwrap();
+        render(
+            &source,
+            &target,
+            &mut handlebars,
+            Sources::default(),
+            json!({"name": "n"}),
+            &Options {
+                layout: Some("base".to_owned()),
+                ..Options::default()
+            },
+        )
+        .expect("couldn't render tree");
+
+        assert_eq!(
+            fs::read_to_string(target.join("page.txt")).unwrap(),
+            "[hi n]"
+        );
+        assert_eq!(fs::read_to_string(target.join("own.txt")).unwrap(), "(own)");
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
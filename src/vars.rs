@@ -0,0 +1,627 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::AppError;
+use crate::formats::{FormatOptions, VarsFormat};
+use crate::input;
+
+/// What to do when two vars files define the same array.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ArrayMerge {
+    /// The array of the later file replaces the earlier one
+    Replace,
+    /// The elements of the later file are appended to the earlier ones
+    Append,
+    /// Elements at the same position are merged together
+    MergeByIndex,
+}
+
+/// One step of a [`VarPath`].
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+/// Path to a value inside the vars, written like `servers[2].port`.
+///
+/// `\` escapes a `.` or `[` that is part of a key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VarPath(pub Vec<Segment>);
+
+impl FromStr for VarPath {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut key = String::new();
+        // whether the last thing parsed was an index, so a `.` or `[` can follow without a key
+        let mut after_index = false;
+        let mut chars = s.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some(escaped) => key.push(escaped),
+                    None => return Err(format!("\"{s}\" ends with a dangling `\\`")),
+                },
+                '.' => {
+                    if key.is_empty() && !after_index {
+                        return Err(format!("\"{s}\" has an empty key"));
+                    }
+                    if !key.is_empty() {
+                        segments.push(Segment::Key(std::mem::take(&mut key)));
+                    }
+                    after_index = false;
+                }
+                '[' => {
+                    if !key.is_empty() {
+                        segments.push(Segment::Key(std::mem::take(&mut key)));
+                    } else if segments.is_empty() {
+                        return Err(format!("\"{s}\" starts with an index"));
+                    }
+                    let mut index = String::new();
+                    loop {
+                        match chars.next() {
+                            Some(']') => break,
+                            Some(c) => index.push(c),
+                            None => return Err(format!("\"{s}\" has an unclosed `[`")),
+                        }
+                    }
+                    let index = index
+                        .parse()
+                        .map_err(|_| format!("\"{s}\" has an invalid index [{index}]"))?;
+                    segments.push(Segment::Index(index));
+                    after_index = true;
+                }
+                c => {
+                    if after_index && key.is_empty() {
+                        return Err(format!("\"{s}\" needs a `.` after an index"));
+                    }
+                    key.push(c);
+                }
+            }
+        }
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        } else if !after_index {
+            return Err(format!("\"{s}\" isn't a valid path"));
+        }
+        Ok(VarPath(segments))
+    }
+}
+
+impl fmt::Display for VarPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.0.iter().enumerate() {
+            match segment {
+                Segment::Key(key) => {
+                    if i > 0 {
+                        write!(f, ".")?;
+                    }
+                    write!(f, "{}", key.replace('.', "\\.").replace('[', "\\["))?;
+                }
+                Segment::Index(index) => write!(f, "[{index}]")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Vars built by layering several sources on top of each other, remembering which source set
+/// each value.
+pub struct Vars {
+    pub value: Value,
+    sources: Vec<String>,
+    /// For every leaf value, the sources that set it (the last one is the one that stuck). Only
+    /// kept by [`Vars::with_origins`], as it's needed just for `--explain-var`.
+    origins: Option<BTreeMap<Vec<Segment>, Vec<usize>>>,
+}
+
+impl Default for Vars {
+    fn default() -> Self {
+        Self {
+            value: Value::Object(Map::new()),
+            sources: Vec::new(),
+            origins: None,
+        }
+    }
+}
+
+impl Vars {
+    /// Empty vars that remember where each value came from, for [`Vars::origins`].
+    pub fn with_origins() -> Self {
+        Self {
+            origins: Some(BTreeMap::new()),
+            ..Self::default()
+        }
+    }
+
+    /// Deep merges `layer` on top of the current vars: objects are merged key by key, arrays
+    /// according to `arrays` and anything else is replaced.
+    pub fn add_layer(&mut self, source: impl Into<String>, layer: Value, arrays: ArrayMerge) {
+        self.sources.push(source.into());
+        let mut merger = Merger {
+            layer: self.sources.len() - 1,
+            arrays,
+            origins: self.origins.as_mut(),
+            path: Vec::new(),
+        };
+        merger.merge(&mut self.value, layer);
+    }
+
+    /// Sets the value at `path`, turning whatever is in the way into the objects and arrays
+    /// needed to get there. Arrays are padded with `null`s when `path` indexes past their end.
+    pub fn set(&mut self, source: impl Into<String>, path: &VarPath, value: Value) {
+        self.sources.push(source.into());
+        let layer = self.sources.len() - 1;
+
+        let mut target = &mut self.value;
+        for (depth, segment) in path.0.iter().enumerate() {
+            target = match segment {
+                Segment::Key(key) => {
+                    if !target.is_object() {
+                        if let Some(origins) = &mut self.origins {
+                            take_origins(origins, &path.0[..depth]);
+                        }
+                        *target = Value::Object(Map::new());
+                    }
+                    let Value::Object(map) = target else {
+                        unreachable!("target was just made an object")
+                    };
+                    map.entry(key.clone()).or_insert(Value::Null)
+                }
+                Segment::Index(index) => {
+                    if !target.is_array() {
+                        if let Some(origins) = &mut self.origins {
+                            take_origins(origins, &path.0[..depth]);
+                        }
+                        *target = Value::Array(Vec::new());
+                    }
+                    let Value::Array(items) = target else {
+                        unreachable!("target was just made an array")
+                    };
+                    if items.len() <= *index {
+                        items.resize(index + 1, Value::Null);
+                    }
+                    &mut items[*index]
+                }
+            };
+        }
+
+        let mut merger = Merger {
+            layer,
+            arrays: ArrayMerge::Replace,
+            origins: self.origins.as_mut(),
+            path: path.0.clone(),
+        };
+        merger.replace_origins(&value);
+        *target = value;
+    }
+
+    /// Where every leaf value at `path` (or below it) came from. When nothing defines `path`,
+    /// a single [`Origin`] without source says so.
+    pub fn origins(&self, path: &VarPath) -> Vec<Origin> {
+        let mut origins: Vec<_> = self
+            .origins
+            .as_ref()
+            .expect("origins are only asked for vars that keep them")
+            .range(path.0.clone()..)
+            .take_while(|(leaf, _)| leaf.starts_with(&path.0))
+            .map(|(leaf, layers)| {
+                let (last, previous) = layers.split_last().expect("origins are never empty");
+                Origin {
+                    path: VarPath(leaf.clone()).to_string(),
+                    value: Some(lookup(&self.value, leaf).unwrap_or(&Value::Null).clone()),
+                    source: Some(self.sources[*last].clone()),
+                    overrides: previous
+                        .iter()
+                        .rev()
+                        .map(|layer| self.sources[*layer].clone())
+                        .collect(),
+                }
+            })
+            .collect();
+        if origins.is_empty() {
+            origins.push(Origin {
+                path: path.to_string(),
+                value: None,
+                source: None,
+                overrides: Vec::new(),
+            });
+        }
+        origins
+    }
+}
+
+/// Where a value came from, as reported by `--explain-var`.
+#[derive(Debug, Serialize)]
+pub struct Origin {
+    pub path: String,
+    pub value: Option<Value>,
+    /// The source whose value stuck, `None` if no source defines `path`.
+    pub source: Option<String>,
+    /// Sources whose values were overridden, most recent first.
+    pub overrides: Vec<String>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (Some(value), Some(source)) = (&self.value, &self.source) else {
+            return write!(f, "{} isn't defined in any vars file", self.path);
+        };
+        write!(f, "{} = {value} (from {source}", self.path)?;
+        if !self.overrides.is_empty() {
+            write!(f, ", overrides {}", self.overrides.join(", "))?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Name of the JSON type of `value`, for error messages.
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    }
+}
+
+/// Deep merges `overlay` into `target` like [`Vars::add_layer`] does, without keeping track of
+/// where the values came from (so nothing is allocated per leaf).
+pub fn merge(target: &mut Value, overlay: Value, arrays: ArrayMerge) {
+    let mut merger = Merger {
+        layer: 0,
+        arrays,
+        origins: None,
+        path: Vec::new(),
+    };
+    merger.merge(target, overlay);
+}
+
+struct Merger<'a> {
+    layer: usize,
+    arrays: ArrayMerge,
+    /// Where to record which layer set each leaf, if anywhere.
+    origins: Option<&'a mut BTreeMap<Vec<Segment>, Vec<usize>>>,
+    path: Vec<Segment>,
+}
+
+impl Merger<'_> {
+    fn merge(&mut self, target: &mut Value, overlay: Value) {
+        match (target, overlay) {
+            (Value::Object(target), Value::Object(overlay)) => {
+                for (key, value) in overlay {
+                    self.path.push(Segment::Key(key.clone()));
+                    match target.get_mut(&key) {
+                        Some(existing) => self.merge(existing, value),
+                        None => {
+                            self.record(&value);
+                            target.insert(key, value);
+                        }
+                    }
+                    self.path.pop();
+                }
+            }
+            (Value::Array(target), Value::Array(overlay)) if self.arrays != ArrayMerge::Replace => {
+                let merge_existing = self.arrays == ArrayMerge::MergeByIndex;
+                let offset = if merge_existing { 0 } else { target.len() };
+                for (i, value) in overlay.into_iter().enumerate() {
+                    self.path.push(Segment::Index(offset + i));
+                    match target.get_mut(offset + i) {
+                        Some(existing) if merge_existing => self.merge(existing, value),
+                        _ => {
+                            self.record(&value);
+                            target.push(value);
+                        }
+                    }
+                    self.path.pop();
+                }
+            }
+            (target, overlay) => {
+                self.replace_origins(&overlay);
+                *target = overlay;
+            }
+        }
+    }
+
+    /// Drops the origins of whatever was at the current path and records `value`'s.
+    fn replace_origins(&mut self, value: &Value) {
+        let Some(origins) = self.origins.as_deref_mut() else {
+            return;
+        };
+        let previous = take_origins(origins, &self.path);
+        self.record(value);
+        // keep the history of leaves that were overridden in place
+        for (leaf, mut layers) in previous {
+            let current = self.origins.as_deref_mut().and_then(|o| o.get_mut(&leaf));
+            if let Some(current) = current {
+                layers.append(current);
+                *current = layers;
+            }
+        }
+    }
+
+    /// Marks every leaf of `value` (placed at the current path) as set by this layer.
+    fn record(&mut self, value: &Value) {
+        if self.origins.is_none() {
+            // nobody asked where the values came from
+            return;
+        }
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, value) in map {
+                    self.path.push(Segment::Key(key.clone()));
+                    self.record(value);
+                    self.path.pop();
+                }
+            }
+            Value::Array(items) if !items.is_empty() => {
+                for (i, value) in items.iter().enumerate() {
+                    self.path.push(Segment::Index(i));
+                    self.record(value);
+                    self.path.pop();
+                }
+            }
+            _ => {
+                if let Some(origins) = self.origins.as_deref_mut() {
+                    origins
+                        .entry(self.path.clone())
+                        .or_default()
+                        .push(self.layer);
+                }
+            }
+        }
+    }
+}
+
+/// Removes and returns the origins of `prefix` and everything below it.
+fn take_origins(
+    origins: &mut BTreeMap<Vec<Segment>, Vec<usize>>,
+    prefix: &[Segment],
+) -> BTreeMap<Vec<Segment>, Vec<usize>> {
+    let leaves: Vec<_> = origins
+        .range(prefix.to_vec()..)
+        .take_while(|(leaf, _)| leaf.starts_with(prefix))
+        .map(|(leaf, _)| leaf.clone())
+        .collect();
+    leaves
+        .into_iter()
+        .filter_map(|leaf| origins.remove_entry(&leaf))
+        .collect()
+}
+
+/// Value at `path` inside `value`, if there's one.
+pub fn lookup<'a>(value: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        Segment::Key(key) => value.get(key),
+        Segment::Index(index) => value.get(index),
+    })
+}
+
+/// Like [`lookup`], but mutable.
+pub fn lookup_mut<'a>(value: &'a mut Value, path: &[Segment]) -> Option<&'a mut Value> {
+    path.iter().try_fold(value, |value, segment| match segment {
+        Segment::Key(key) => value.get_mut(key),
+        Segment::Index(index) => value.get_mut(index),
+    })
+}
+
+/// Process environment variables whose name starts with `prefix`, as a flat object.
+///
+/// Variables whose name or value isn't valid unicode are skipped.
+pub fn environment(prefix: &str) -> Value {
+    let vars = std::env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| name.starts_with(prefix))
+        .map(|(name, value)| (name, Value::String(value)))
+        .collect::<Map<_, _>>();
+    Value::Object(vars)
+}
+
+/// Reads and parses the vars file at `path` (`-` is stdin).
+///
+/// Unless `format` is given, it's guessed from the file extension, falling back to JSON.
+pub fn load_file(
+    path: &str,
+    format: Option<VarsFormat>,
+    options: &FormatOptions,
+) -> Result<Value, AppError> {
+    let format = format
+        .or_else(|| VarsFormat::from_path(path))
+        .unwrap_or(VarsFormat::Json);
+    let contents = input::read_to_string(path)?;
+    format
+        .parse(&contents, options)
+        .map_err(|source| AppError::Vars {
+            path: input::display_name(path).to_owned(),
+            contents,
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::{environment, merge, ArrayMerge, Segment, VarPath, Vars};
+
+    fn merged(arrays: ArrayMerge) -> Vars {
+        let mut vars = Vars::with_origins();
+        vars.add_layer(
+            "base.json",
+            json!({"image": {"name": "app", "tag": "latest"}, "ports": [{"n": 80}, {"n": 443}]}),
+            arrays,
+        );
+        vars.add_layer(
+            "prod.json",
+            json!({"image": {"tag": "1.2.3"}, "ports": [{"tls": true}]}),
+            arrays,
+        );
+        vars
+    }
+
+    #[test]
+    fn objects_are_deep_merged() {
+        let vars = merged(ArrayMerge::Replace);
+        assert_eq!(
+            vars.value,
+            json!({"image": {"name": "app", "tag": "1.2.3"}, "ports": [{"tls": true}]})
+        );
+    }
+
+    #[test]
+    fn arrays_can_be_appended_or_merged_by_index() {
+        let vars = merged(ArrayMerge::Append);
+        assert_eq!(
+            vars.value["ports"],
+            json!([{"n": 80}, {"n": 443}, {"tls": true}])
+        );
+
+        let vars = merged(ArrayMerge::MergeByIndex);
+        assert_eq!(
+            vars.value["ports"],
+            json!([{"n": 80, "tls": true}, {"n": 443}])
+        );
+    }
+
+    /// What `--explain-var` prints for `path`.
+    fn explain(vars: &Vars, path: &VarPath) -> String {
+        vars.origins(path)
+            .iter()
+            .map(|origin| format!("{origin}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn explain_reports_origin_and_overrides() {
+        let vars = merged(ArrayMerge::Replace);
+        let path = "image".parse::<VarPath>().expect("valid path");
+        assert_eq!(
+            explain(&vars, &path),
+            "image.name = \"app\" (from base.json)\n\
+             image.tag = \"1.2.3\" (from prod.json, overrides base.json)\n"
+        );
+    }
+
+    #[test]
+    fn origins_serialize_for_json_diagnostics() {
+        let vars = merged(ArrayMerge::Replace);
+        let origins = vars.origins(&"image.tag".parse().expect("valid path"));
+        assert_eq!(
+            serde_json::to_value(&origins).unwrap(),
+            json!([{
+                "path": "image.tag",
+                "value": "1.2.3",
+                "source": "prod.json",
+                "overrides": ["base.json"],
+            }])
+        );
+        let missing = vars.origins(&"nope".parse().expect("valid path"));
+        assert_eq!(missing[0].source, None);
+    }
+
+    #[test]
+    fn merge_without_origins() {
+        let mut target = json!({"a": {"b": 1, "c": [1]}, "d": "x"});
+        merge(
+            &mut target,
+            json!({"a": {"c": [2], "e": {"f": true}}, "d": null}),
+            ArrayMerge::Append,
+        );
+        assert_eq!(
+            target,
+            json!({"a": {"b": 1, "c": [1, 2], "e": {"f": true}}, "d": null})
+        );
+    }
+
+    #[test]
+    fn environment_is_filtered_by_prefix() {
+        let path = std::env::var("PATH").expect("PATH is set");
+        let vars = environment("PATH");
+        assert_eq!(vars["PATH"], json!(path));
+        assert!(vars
+            .as_object()
+            .expect("an object")
+            .keys()
+            .all(|name| name.starts_with("PATH")));
+        assert_eq!(environment("TEMPLATIER_TEST_UNSET_"), json!({}));
+    }
+
+    #[test]
+    fn origins_are_only_kept_when_asked_for() {
+        let mut vars = Vars::default();
+        vars.add_layer("base.json", json!({"a": {"b": 1}}), ArrayMerge::Replace);
+        vars.set("--set a.c", &"a.c".parse().expect("valid path"), json!(2));
+        assert_eq!(vars.value, json!({"a": {"b": 1, "c": 2}}));
+        assert!(vars.origins.is_none());
+    }
+
+    #[test]
+    fn set_patches_nested_values() {
+        let mut vars = Vars::with_origins();
+        vars.add_layer(
+            "base.json",
+            json!({"servers": [{"port": 80}, {"port": 81}], "name": "x"}),
+            ArrayMerge::Replace,
+        );
+        vars.set(
+            "--set servers[1].port",
+            &"servers[1].port".parse().expect("valid path"),
+            json!(8080),
+        );
+        vars.set(
+            "--set servers[3].port",
+            &"servers[3].port".parse().expect("valid path"),
+            json!(9090),
+        );
+        vars.set(
+            "--set name.first",
+            &"name.first".parse().expect("valid path"),
+            json!("john"),
+        );
+
+        assert_eq!(
+            vars.value,
+            json!({
+                "servers": [{"port": 80}, {"port": 8080}, null, {"port": 9090}],
+                "name": {"first": "john"},
+            })
+        );
+        assert_eq!(
+            explain(&vars, &"servers[1]".parse().expect("valid path")),
+            "servers[1].port = 8080 (from --set servers[1].port, overrides base.json)\n"
+        );
+    }
+
+    #[test]
+    fn paths_support_indices_and_escapes() {
+        let path = "servers[2].port".parse::<VarPath>().expect("valid path");
+        assert_eq!(
+            path.0,
+            vec![
+                Segment::Key("servers".to_owned()),
+                Segment::Index(2),
+                Segment::Key("port".to_owned()),
+            ]
+        );
+        let path = r"labels.app\.kubernetes\.io/name"
+            .parse::<VarPath>()
+            .expect("valid path");
+        assert_eq!(path.0.len(), 2);
+        assert_eq!(path.to_string(), r"labels.app\.kubernetes\.io/name");
+
+        assert!("a..b".parse::<VarPath>().is_err());
+        assert!("a[x]".parse::<VarPath>().is_err());
+        assert!("a[1]b".parse::<VarPath>().is_err());
+        assert!("a[1".parse::<VarPath>().is_err());
+    }
+}